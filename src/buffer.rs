@@ -0,0 +1,178 @@
+use std::{mem, ops::Range};
+
+/// A GPU buffer together with a CPU side copy of its contents.
+/// When the data outgrows the buffer its capacity is doubled, otherwise only the range that changed since the last [`GrowableBuffer::flush`] is uploaded.
+pub struct GrowableBuffer<T: bytemuck::Pod> {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    data: Vec<T>,
+    dirty: Option<Range<usize>>,
+}
+
+impl<T: bytemuck::Pod> GrowableBuffer<T> {
+    pub fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        data: Vec<T>,
+    ) -> Self {
+        let capacity = data.len().max(1);
+        let buffer = create_buffer(device, label, usage, capacity, &data);
+
+        Self {
+            label,
+            usage,
+            buffer,
+            capacity,
+            data,
+            dirty: None,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn push(&mut self, value: T) -> usize {
+        let index = self.data.len();
+        self.data.push(value);
+        self.mark_dirty(index..index + 1);
+
+        index
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        self.data[index] = value;
+        self.mark_dirty(index..index + 1);
+    }
+
+    /// Overwrites the elements starting at `offset`, extending the data if necessary.
+    pub fn write(&mut self, offset: usize, values: &[T]) {
+        let end = offset + values.len();
+        if end > self.data.len() {
+            self.data.resize(end, T::zeroed());
+        }
+        self.data[offset..end].copy_from_slice(values);
+        self.mark_dirty(offset..end);
+    }
+
+    /// Replaces all elements. The buffer keeps its capacity if the new data fits into it.
+    pub fn replace(&mut self, values: &[T]) {
+        self.data.clear();
+        self.data.extend_from_slice(values);
+        self.mark_dirty(0..values.len());
+    }
+
+    /// Removes the element at `index` and replaces it with the last element.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        let value = self.data.swap_remove(index);
+        if index < self.data.len() {
+            self.mark_dirty(index..index + 1);
+        }
+
+        value
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn mark_dirty(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Uploads pending changes. Returns `true` if the [`wgpu::Buffer`] had to be recreated, which invalidates bind groups referencing it.
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        if self.data.len() > self.capacity {
+            while self.capacity < self.data.len() {
+                self.capacity *= 2;
+            }
+            self.buffer = create_buffer(device, self.label, self.usage, self.capacity, &self.data);
+            self.dirty = None;
+
+            return true;
+        }
+
+        if let Some(dirty) = self.dirty.take() {
+            let end = dirty.end.min(self.data.len());
+            if dirty.start < end {
+                queue.write_buffer(
+                    &self.buffer,
+                    (dirty.start * mem::size_of::<T>()) as wgpu::BufferAddress,
+                    bytemuck::cast_slice(&self.data[dirty.start..end]),
+                );
+            }
+        }
+
+        false
+    }
+}
+
+fn create_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    capacity: usize,
+    data: &[T],
+) -> wgpu::Buffer {
+    let size = wgpu::util::align_to(
+        (capacity * mem::size_of::<T>()) as wgpu::BufferAddress,
+        wgpu::COPY_BUFFER_ALIGNMENT,
+    );
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: true,
+    });
+    let contents: &[u8] = bytemuck::cast_slice(data);
+    buffer.slice(..).get_mapped_range_mut()[..contents.len()].copy_from_slice(contents);
+    buffer.unmap();
+
+    buffer
+}
+
+/// Copies a buffer with `COPY_SRC` usage back to the CPU, this waits until the GPU finished all submitted work.
+pub(crate) fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+) -> anyhow::Result<Vec<u8>> {
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+
+    map_read(device, &readback)
+}
+
+/// Maps a buffer with `MAP_READ` usage and copies its contents, this waits until the GPU finished all submitted work.
+pub(crate) fn map_read(device: &wgpu::Device, buffer: &wgpu::Buffer) -> anyhow::Result<Vec<u8>> {
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        // The receiver only goes away once the result arrived
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv()??;
+
+    let data = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    Ok(data)
+}
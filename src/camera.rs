@@ -1,4 +1,4 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 
 /*
 #[rustfmt::skip]
@@ -23,19 +23,90 @@ pub struct Camera {
 
 impl Camera {
     pub fn build_view_projection_matrix(&self, aspect: f32) -> Mat4 {
+        self.build_shifted_view_projection_matrix(aspect, Vec2::ZERO)
+    }
+
+    /// Like `build_view_projection_matrix()`, but shifts the projection by `lens_shift` in normalized device coordinates,
+    /// which results in an asymmetric frustum. Used for the eyes of a [`StereoCamera`].
+    pub fn build_shifted_view_projection_matrix(&self, aspect: f32, lens_shift: Vec2) -> Mat4 {
         let view = Mat4::look_at_rh(self.eye, self.target, self.up);
-        let proj = if self.viewport.is_some() {
-            Mat4::perspective_rh(
-                self.fovy,
-                self.viewport.as_ref().unwrap().w / self.viewport.as_ref().unwrap().h,
-                self.znear,
-                self.zfar,
-            )
+        let proj = if let Some(viewport) = &self.viewport {
+            Mat4::perspective_rh(self.fovy, viewport.w / viewport.h, self.znear, self.zfar)
         } else {
             Mat4::perspective_rh(self.fovy, aspect, self.znear, self.zfar)
         };
 
-        proj * view
+        Mat4::from_translation(lens_shift.extend(0.0)) * proj * view
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// A pair of cameras for stereo rendering, built from the camera in between the eyes.
+/// The eyes look in parallel and their frusta are shifted to converge at `convergence`, objects at that distance appear at the same position for both eyes.
+#[derive(Clone, Copy)]
+pub struct StereoCamera {
+    pub camera: Camera,
+    /// The interpupillary distance, the distance between the eyes
+    pub ipd: f32,
+    /// Has to be positive, see `validate()`
+    pub convergence: f32,
+}
+
+impl StereoCamera {
+    /// Fails unless the convergence distance is positive, which `eye()` divides by.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.convergence.is_nan() || self.convergence <= 0.0 {
+            anyhow::bail!(
+                "the convergence distance has to be positive, not {}",
+                self.convergence
+            );
+        }
+        Ok(())
+    }
+
+    /// Builds the camera of one eye, rendering into its half of a side-by-side image of the given size,
+    /// together with the lens shift of its asymmetric frustum, see [`Camera::build_shifted_view_projection_matrix`].
+    pub fn eye(&self, eye: Eye, width: f32, height: f32) -> (Camera, Vec2) {
+        let forward = (self.camera.target - self.camera.eye).normalize();
+        let right = forward.cross(self.camera.up).normalize();
+        let side = match eye {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        };
+        let offset = right * self.ipd * 0.5 * side;
+
+        let viewport = Viewport {
+            x: match eye {
+                Eye::Left => 0.0,
+                Eye::Right => width * 0.5,
+            },
+            y: 0.0,
+            w: width * 0.5,
+            h: height,
+        };
+        // Moves the point at the convergence distance back to the center of the eye's image
+        let focal_length = 1.0 / ((self.camera.fovy * 0.5).tan() * viewport.w / viewport.h);
+        let shift = side * focal_length * self.ipd * 0.5 / self.convergence;
+
+        let camera = Camera {
+            eye: self.camera.eye + offset,
+            target: self.camera.target + offset,
+            viewport: Some(viewport),
+            ..self.camera
+        };
+        (camera, Vec2::new(shift, 0.0))
+    }
+
+    pub fn eyes(&self, width: f32, height: f32) -> [(Camera, Vec2); 2] {
+        [
+            self.eye(Eye::Left, width, height),
+            self.eye(Eye::Right, width, height),
+        ]
     }
 }
 
@@ -44,6 +115,7 @@ impl Camera {
 pub struct CameraUniform {
     view_position: [f32; 4],
     view_projection: [[f32; 4]; 4],
+    inverse_view_projection: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -51,13 +123,27 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_projection: Mat4::IDENTITY.to_cols_array_2d(),
+            inverse_view_projection: Mat4::IDENTITY.to_cols_array_2d(),
         }
     }
 
     pub fn update_view_projection(&mut self, camera: &Camera, aspect: f32) {
+        self.update_shifted_view_projection(camera, aspect, Vec2::ZERO);
+    }
+
+    /// Like `update_view_projection()` with the lens shift of an eye of a [`StereoCamera`].
+    pub fn update_shifted_view_projection(
+        &mut self,
+        camera: &Camera,
+        aspect: f32,
+        lens_shift: Vec2,
+    ) {
         // We're using Vector4 because of the uniforms 16 byte spacing requirement
         self.view_position = [camera.eye.x, camera.eye.y, camera.eye.z, 1.0];
-        self.view_projection = (camera.build_view_projection_matrix(aspect)).to_cols_array_2d();
+        let view_projection = camera.build_shifted_view_projection_matrix(aspect, lens_shift);
+        self.view_projection = view_projection.to_cols_array_2d();
+        // Used for reconstructing rays when raymarching
+        self.inverse_view_projection = view_projection.inverse().to_cols_array_2d();
     }
 }
 
@@ -0,0 +1,6 @@
+// Matches `CameraUniform`
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
@@ -0,0 +1,159 @@
+// Compute shader
+
+struct Cull {
+    planes: array<vec4<f32>, 6>,
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    // The view projection the Hi-Z pyramid was rendered with
+    previous_view_proj: mat4x4<f32>,
+    // The viewport of the camera in pixels
+    viewport: vec4<f32>,
+    instance_count: u32,
+    occlusion: u32,
+    hiz_mip_count: u32,
+    // Every mesh writes its instances into its own range of this many instances in instances_out
+    instance_capacity: u32,
+}
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+
+// InstanceRaw is 25 floats, which doesn't match the layout of any WGSL struct
+const INSTANCE_SIZE: u32 = 25u;
+@group(0) @binding(1)
+var<storage, read> instances_in: array<f32>;
+@group(0) @binding(2)
+var<storage, read_write> instances_out: array<f32>;
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+@group(0) @binding(3)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+struct MeshBounds {
+    min: vec4<f32>,
+    max: vec4<f32>,
+}
+@group(0) @binding(4)
+var<storage, read> mesh_bounds: array<MeshBounds>;
+
+fn instance_matrix(instance: u32) -> mat4x4<f32> {
+    let base = instance * INSTANCE_SIZE;
+    return mat4x4<f32>(
+        vec4<f32>(instances_in[base], instances_in[base + 1u], instances_in[base + 2u], instances_in[base + 3u]),
+        vec4<f32>(instances_in[base + 4u], instances_in[base + 5u], instances_in[base + 6u], instances_in[base + 7u]),
+        vec4<f32>(instances_in[base + 8u], instances_in[base + 9u], instances_in[base + 10u], instances_in[base + 11u]),
+        vec4<f32>(instances_in[base + 12u], instances_in[base + 13u], instances_in[base + 14u], instances_in[base + 15u]),
+    );
+}
+
+@group(1) @binding(0)
+var hiz: texture_2d<f32>;
+
+fn is_in_frustum(world_center: vec3<f32>, world_extents: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i++) {
+        let plane = cull.planes[i];
+        // The distance of the corner furthest along the plane normal
+        let distance = dot(plane.xyz, world_center) + dot(abs(plane.xyz), world_extents) + plane.w;
+        if distance < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn is_occluded(world_center: vec3<f32>, world_extents: vec3<f32>) -> bool {
+    if cull.occlusion == 0u {
+        return false;
+    }
+
+    // Project the box into the previous frame
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner_sign = vec3<f32>(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = cull.previous_view_proj * vec4<f32>(world_center + world_extents * corner_sign, 1.0);
+        // Boxes crossing the near plane are never occluded
+        if clip.w <= 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = min(nearest, ndc.z);
+    }
+    // Boxes that weren't completely on screen could be visible now
+    if any(uv_min < vec2<f32>(0.0)) || any(uv_max > vec2<f32>(1.0)) {
+        return false;
+    }
+
+    // Pick the mip level where the box covers at most 2x2 texels
+    let pixel_min = cull.viewport.xy + uv_min * cull.viewport.zw;
+    let pixel_max = cull.viewport.xy + uv_max * cull.viewport.zw;
+    let extent = max(pixel_max.x - pixel_min.x, pixel_max.y - pixel_min.y);
+    let mip = min(u32(ceil(log2(max(extent, 1.0)))), cull.hiz_mip_count - 1u);
+
+    let mip_size = vec2<i32>(textureDimensions(hiz, mip));
+    let scale = f32(1u << mip);
+    let texel_min = clamp(vec2<i32>(pixel_min / scale), vec2<i32>(0), mip_size - 1);
+    let texel_max = clamp(vec2<i32>(pixel_max / scale), vec2<i32>(0), mip_size - 1);
+
+    var furthest = 0.0;
+    for (var y = texel_min.y; y <= texel_max.y; y++) {
+        for (var x = texel_min.x; x <= texel_max.x; x++) {
+            furthest = max(furthest, textureLoad(hiz, vec2<i32>(x, y), i32(mip)).r);
+        }
+    }
+    return nearest > furthest;
+}
+
+fn is_visible(model_matrix: mat4x4<f32>, bounds_min: vec3<f32>, bounds_max: vec3<f32>) -> bool {
+    // Transform the bounds into world space
+    let center = (bounds_min + bounds_max) * 0.5;
+    let half_extents = (bounds_max - bounds_min) * 0.5;
+    let world_center = (model_matrix * vec4<f32>(center, 1.0)).xyz;
+    let world_extents = abs(model_matrix[0].xyz) * half_extents.x
+        + abs(model_matrix[1].xyz) * half_extents.y
+        + abs(model_matrix[2].xyz) * half_extents.z;
+
+    return is_in_frustum(world_center, world_extents) && !is_occluded(world_center, world_extents);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    if instance >= cull.instance_count {
+        return;
+    }
+
+    let model_matrix = instance_matrix(instance);
+    if !is_visible(model_matrix, cull.bounds_min.xyz, cull.bounds_max.xyz) {
+        return;
+    }
+
+    // The bounds of a single mesh are the bounds of the model
+    let mesh_count = arrayLength(&draws);
+    let test_meshes = mesh_count > 1u;
+    let src = instance * INSTANCE_SIZE;
+    for (var mesh = 0u; mesh < mesh_count; mesh++) {
+        if test_meshes && !is_visible(model_matrix, mesh_bounds[mesh].min.xyz, mesh_bounds[mesh].max.xyz) {
+            continue;
+        }
+
+        let slot = atomicAdd(&draws[mesh].instance_count, 1u);
+        let dst = (mesh * cull.instance_capacity + slot) * INSTANCE_SIZE;
+        for (var i = 0u; i < INSTANCE_SIZE; i++) {
+            instances_out[dst + i] = instances_in[src + i];
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::{mem, ops::Range};
+
+use glam::{Mat4, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    hiz::HiZPyramid,
+    instance::{InstanceBuffer, InstanceRaw},
+    model::Model,
+};
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Returns the smallest [`Aabb`] containing all `points`, or [`None`] if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        Some(points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        ))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Returns the [`Aabb`] enclosing this box after transforming it by `matrix`.
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        let extents = matrix.x_axis.truncate().abs() * half_extents.x
+            + matrix.y_axis.truncate().abs() * half_extents.y
+            + matrix.z_axis.truncate().abs() * half_extents.z;
+
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// The six planes of a camera frustum, pointing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix like the one from [`Camera::build_view_projection_matrix`](crate::camera::Camera::build_view_projection_matrix).
+    /// wgpu's depth range of `0..1` is taken into account for the near plane.
+    pub fn from_view_projection(view_projection: &Mat4) -> Self {
+        let row0 = view_projection.row(0);
+        let row1 = view_projection.row(1);
+        let row2 = view_projection.row(2);
+        let row3 = view_projection.row(3);
+
+        let planes = [
+            row3 + row0,
+            row3 - row0,
+            row3 + row1,
+            row3 - row1,
+            row2,
+            row3 - row2,
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    /// Returns `false` if the [`Aabb`] is completely outside of the frustum.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+/// Where the instances are culled against the camera frustums.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CullingMode {
+    /// Instances and their meshes are culled and compacted by a compute shader and drawn using indirect draws, so the CPU never touches them.
+    /// No [`CullingStats`] are collected.
+    /// This also allows for occlusion culling using the depth of the previous frame.
+    #[default]
+    Gpu,
+    /// A fallback for devices without compute shaders or indirect draws, which culls instances and meshes on the CPU before issuing the draws.
+    /// This collects [`CullingStats`].
+    Cpu,
+}
+
+/// How many instances a camera culled during the last `render()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    /// Visible instances that were tested against the frustum.
+    pub instances: u32,
+    /// Instances whose [`Model`] bounds were outside the frustum.
+    pub instances_culled: u32,
+    /// Meshes of otherwise drawn instances that were outside the frustum.
+    pub meshes_culled: u32,
+}
+
+impl CullingStats {
+    pub fn instances_drawn(&self) -> u32 {
+        self.instances - self.instances_culled
+    }
+}
+
+/// Tests all visible instances of a [`Model`] against the frustum and returns the ranges of instances to draw for every mesh.
+pub(crate) fn cull_model(
+    frustum: &Frustum,
+    model: &Model,
+    instance_buffer: &InstanceBuffer,
+    stats: &mut CullingStats,
+) -> Vec<Vec<Range<u32>>> {
+    let mut mesh_ranges: Vec<Vec<Range<u32>>> = vec![Vec::new(); model.meshes.len()];
+    let test_meshes = model.meshes.len() > 1;
+
+    for (slot, instance_id) in instance_buffer.instance_ids().iter().enumerate() {
+        stats.instances += 1;
+
+        let matrix = model.instances[*instance_id].model_matrix();
+        if !frustum.intersects_aabb(&model.bounds.transform(&matrix)) {
+            stats.instances_culled += 1;
+            continue;
+        }
+
+        for (mesh, ranges) in model.meshes.iter().zip(&mut mesh_ranges) {
+            if test_meshes && !frustum.intersects_aabb(&mesh.bounds.transform(&matrix)) {
+                stats.meshes_culled += 1;
+                continue;
+            }
+
+            let slot = slot as u32;
+            match ranges.last_mut() {
+                Some(range) if range.end == slot => range.end += 1,
+                _ => ranges.push(slot..slot + 1),
+            }
+        }
+    }
+
+    mesh_ranges
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    bounds_min: [f32; 4],
+    bounds_max: [f32; 4],
+    previous_view_projection: [[f32; 4]; 4],
+    viewport: [f32; 4],
+    instance_count: u32,
+    occlusion: u32,
+    hiz_mip_count: u32,
+    instance_capacity: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MeshBoundsRaw {
+    min: [f32; 4],
+    max: [f32; 4],
+}
+
+/// The GPU resources for culling the instances of one [`Model`] for one camera.
+/// The compute shader tests every instance against the bounds of every mesh. The surviving instances of a mesh are written into its range of `instance_buffer`,
+/// see `mesh_instances()`, and their count into `indirect_buffer`, which holds one [`wgpu::util::DrawIndexedIndirect`] per mesh.
+pub(crate) struct CullTarget {
+    uniform_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    pub indirect_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    instance_count: u32,
+    /// The size of the range of every mesh in `instance_buffer`
+    mesh_instances_size: wgpu::BufferAddress,
+    // The view projection of the last frame, which the Hi-Z pyramid was rendered with
+    previous_view_projection: Option<Mat4>,
+}
+
+impl CullTarget {
+    pub const INDIRECT_SIZE: wgpu::BufferAddress =
+        mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        model: &Model,
+        instances: &InstanceBuffer,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            size: mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mesh_instances_size = instances.buffer().size();
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culled Instance Buffer"),
+            size: mesh_instances_size * model.meshes.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let mesh_bounds = model
+            .meshes
+            .iter()
+            .map(|mesh| MeshBoundsRaw {
+                min: mesh.bounds.min.extend(0.0).to_array(),
+                max: mesh.bounds.max.extend(0.0).to_array(),
+            })
+            .collect::<Vec<_>>();
+        let mesh_bounds_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Bounds Buffer"),
+            contents: bytemuck::cast_slice(&mesh_bounds),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let indirect_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Buffer"),
+            contents: &indirect_args(model),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instances.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: mesh_bounds_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("cull_bind_group"),
+        });
+
+        Self {
+            uniform_buffer,
+            instance_buffer,
+            indirect_buffer,
+            bind_group,
+            instance_count: 0,
+            mesh_instances_size,
+            previous_view_projection: None,
+        }
+    }
+
+    /// Uploads the frustum and resets the instance counts of the indirect draws.
+    /// Occlusion culling is done if a built [`HiZPyramid`] is passed and the target was already used in the previous frame.
+    pub fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        view_projection: Mat4,
+        viewport: [f32; 4],
+        hiz: Option<&HiZPyramid>,
+        model: &Model,
+        instances: &InstanceBuffer,
+    ) {
+        self.instance_count = instances.visible_count();
+        let previous_view_projection = self
+            .previous_view_projection
+            .replace(view_projection)
+            .filter(|_| hiz.is_some_and(|hiz| hiz.built));
+        let uniform = CullUniform {
+            planes: Frustum::from_view_projection(&view_projection)
+                .planes
+                .map(|plane| plane.to_array()),
+            bounds_min: model.bounds.min.extend(0.0).to_array(),
+            bounds_max: model.bounds.max.extend(0.0).to_array(),
+            previous_view_projection: previous_view_projection
+                .unwrap_or(view_projection)
+                .to_cols_array_2d(),
+            viewport,
+            instance_count: self.instance_count,
+            occlusion: previous_view_projection.is_some() as u32,
+            hiz_mip_count: hiz.map_or(1, |hiz| hiz.mip_count),
+            instance_capacity: (self.mesh_instances_size
+                / mem::size_of::<InstanceRaw>() as wgpu::BufferAddress)
+                as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        queue.write_buffer(&self.indirect_buffer, 0, &indirect_args(model));
+    }
+
+    /// The instances of the mesh that survived culling, for the vertex buffer of its indirect draw.
+    pub fn mesh_instances(&self, mesh_index: usize) -> wgpu::BufferSlice<'_> {
+        let start = self.mesh_instances_size * mesh_index as wgpu::BufferAddress;
+        self.instance_buffer
+            .slice(start..start + self.mesh_instances_size)
+    }
+
+    pub fn dispatch<'a>(&'a self, compute_pass: &mut wgpu::ComputePass<'a>) {
+        if self.instance_count > 0 {
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.instance_count.div_ceil(64), 1, 1);
+        }
+    }
+}
+
+fn indirect_args(model: &Model) -> Vec<u8> {
+    model
+        .meshes
+        .iter()
+        .flat_map(|mesh| {
+            wgpu::util::DrawIndexedIndirect {
+                vertex_count: mesh.num_elements,
+                instance_count: 0,
+                base_index: 0,
+                vertex_offset: 0,
+                base_instance: 0,
+            }
+            .as_bytes()
+            .to_vec()
+        })
+        .collect()
+}
@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use glam::{IVec3, Vec3};
+
+use crate::voxel::{VoxelPoint, VoxelStorage, VoxelVolume};
+
+/// The region a [`Brush`] affects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrushShape {
+    Sphere { center: Vec3, radius: f32 },
+    Box { center: Vec3, half_extents: Vec3 },
+}
+
+impl BrushShape {
+    pub fn contains(&self, position: Vec3) -> bool {
+        match *self {
+            Self::Sphere { center, radius } => position.distance_squared(center) <= radius * radius,
+            Self::Box {
+                center,
+                half_extents,
+            } => (position - center).abs().cmple(half_extents).all(),
+        }
+    }
+
+    fn bounds(&self) -> (Vec3, Vec3) {
+        match *self {
+            Self::Sphere { center, radius } => (center - radius, center + radius),
+            Self::Box {
+                center,
+                half_extents,
+            } => (center - half_extents, center + half_extents),
+        }
+    }
+}
+
+/// What a [`Brush`] does to the voxels inside its shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrushOperation {
+    /// Fills the shape with voxels of the given material on a grid with cells of `voxel_size`. Cells that already contain a voxel are left as they are.
+    Add { material: u32, voxel_size: f32 },
+    /// Removes every voxel whose center is inside the shape.
+    Subtract,
+    /// Changes the material of every voxel whose center is inside the shape.
+    Paint { material: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub operation: BrushOperation,
+}
+
+/// The closest voxel hit by a ray, see `RenderState::raycast_voxels()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelHit {
+    pub volume_id: usize,
+    /// Index into the points of the [`VoxelVolume`]
+    pub point_index: usize,
+    pub distance: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+/// A single change to the points of a volume, enough to revert it.
+#[derive(Clone, Copy, Debug)]
+enum Change {
+    Set {
+        index: usize,
+        before: VoxelPoint,
+        after: VoxelPoint,
+    },
+    Push(VoxelPoint),
+    SwapRemove {
+        index: usize,
+        point: VoxelPoint,
+    },
+}
+
+impl Change {
+    fn apply(&self, storage: &mut VoxelStorage, volume_id: usize) {
+        match *self {
+            Self::Set { index, after, .. } => storage.set_point(volume_id, index, after),
+            Self::Push(point) => storage.push_point(volume_id, point),
+            Self::SwapRemove { index, .. } => {
+                storage.swap_remove_point(volume_id, index);
+            }
+        }
+    }
+
+    fn revert(&self, storage: &mut VoxelStorage, volume_id: usize) {
+        match *self {
+            Self::Set { index, before, .. } => storage.set_point(volume_id, index, before),
+            Self::Push(_) => {
+                let last = storage.get(volume_id).points.len() - 1;
+                storage.swap_remove_point(volume_id, last);
+            }
+            // The point that was moved into the slot goes back to the end
+            Self::SwapRemove { index, point } => {
+                let points = &storage.get(volume_id).points;
+                if index < points.len() {
+                    storage.push_point(volume_id, points[index]);
+                    storage.set_point(volume_id, index, point);
+                } else {
+                    storage.push_point(volume_id, point);
+                }
+            }
+        }
+    }
+}
+
+/// All changes one [`Brush`] made to a volume.
+struct VoxelEdit {
+    volume_id: usize,
+    changes: Vec<Change>,
+}
+
+/// The history of brush edits that can be undone and redone.
+#[derive(Default)]
+pub(crate) struct EditLog {
+    undo: Vec<VoxelEdit>,
+    redo: Vec<VoxelEdit>,
+}
+
+impl EditLog {
+    /// Applies the brush to a volume and records the changes. Returns `false` if nothing changed.
+    pub fn apply(&mut self, storage: &mut VoxelStorage, volume_id: usize, brush: &Brush) -> bool {
+        let changes = brush_changes(storage.get(volume_id), brush);
+        if changes.is_empty() {
+            return false;
+        }
+        for change in &changes {
+            change.apply(storage, volume_id);
+        }
+        self.undo.push(VoxelEdit { volume_id, changes });
+        self.redo.clear();
+        true
+    }
+
+    /// Reverts the last edit. Returns `false` if there is nothing to undo.
+    pub fn undo(&mut self, storage: &mut VoxelStorage) -> bool {
+        let Some(edit) = self.undo.pop() else {
+            return false;
+        };
+        for change in edit.changes.iter().rev() {
+            change.revert(storage, edit.volume_id);
+        }
+        self.redo.push(edit);
+        true
+    }
+
+    /// Applies the last undone edit again. Returns `false` if there is nothing to redo.
+    pub fn redo(&mut self, storage: &mut VoxelStorage) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        for change in &edit.changes {
+            change.apply(storage, edit.volume_id);
+        }
+        self.undo.push(edit);
+        true
+    }
+
+    /// Drops the history of a volume, which is needed once it is replaced or removed.
+    pub fn forget(&mut self, volume_id: usize) {
+        self.undo.retain(|edit| edit.volume_id != volume_id);
+        self.redo.retain(|edit| edit.volume_id != volume_id);
+    }
+}
+
+/// The changes the brush makes to the points of the volume, in the order they have to be applied.
+fn brush_changes(volume: &VoxelVolume, brush: &Brush) -> Vec<Change> {
+    let inside = |point: &VoxelPoint| brush.shape.contains(point.position);
+    match brush.operation {
+        BrushOperation::Add {
+            material,
+            voxel_size,
+        } => {
+            if voxel_size <= 0.0 {
+                return vec![];
+            }
+            let cell = |position: Vec3| (position / voxel_size).floor().as_ivec3();
+            let occupied = volume
+                .points
+                .iter()
+                .map(|point| cell(point.position))
+                .collect::<HashSet<IVec3>>();
+            let (min, max) = brush.shape.bounds();
+            let (cell_min, cell_max) = (cell(min), cell(max));
+            // Large enough to cover the whole cube of the voxel
+            let radius = voxel_size * 0.5 * 3.0_f32.sqrt();
+
+            let mut changes = vec![];
+            for z in cell_min.z..=cell_max.z {
+                for y in cell_min.y..=cell_max.y {
+                    for x in cell_min.x..=cell_max.x {
+                        let cell = IVec3::new(x, y, z);
+                        let position = (cell.as_vec3() + 0.5) * voxel_size;
+                        if brush.shape.contains(position) && !occupied.contains(&cell) {
+                            changes.push(Change::Push(VoxelPoint {
+                                position,
+                                radius,
+                                material,
+                            }));
+                        }
+                    }
+                }
+            }
+            changes
+        }
+        // Going backwards only ever moves points that were already checked
+        BrushOperation::Subtract => (0..volume.points.len())
+            .rev()
+            .filter(|index| inside(&volume.points[*index]))
+            .map(|index| Change::SwapRemove {
+                index,
+                point: volume.points[index],
+            })
+            .collect(),
+        BrushOperation::Paint { material } => volume
+            .points
+            .iter()
+            .enumerate()
+            .filter(|(_, point)| inside(point) && point.material != material)
+            .map(|(index, point)| Change::Set {
+                index,
+                before: *point,
+                after: VoxelPoint { material, ..*point },
+            })
+            .collect(),
+    }
+}
+
+/// Intersects the ray with the spheres of the voxels, ignoring the smooth blending between them.
+pub(crate) fn raycast<'a>(
+    volumes: impl Iterator<Item = (usize, &'a VoxelVolume)>,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<VoxelHit> {
+    let direction = direction.try_normalize()?;
+    let mut closest: Option<VoxelHit> = None;
+
+    for (volume_id, volume) in volumes {
+        for (point_index, point) in volume.points.iter().enumerate() {
+            let to_center = point.position - origin;
+            let along = to_center.dot(direction);
+            let offset_squared = to_center.length_squared() - along * along;
+            let radius_squared = point.radius * point.radius;
+            if offset_squared > radius_squared {
+                continue;
+            }
+            let half_chord = (radius_squared - offset_squared).sqrt();
+            // Voxels behind the origin
+            if along + half_chord < 0.0 {
+                continue;
+            }
+            // Rays starting inside a voxel hit it right away
+            let distance = (along - half_chord).max(0.0);
+            if distance > max_distance || closest.is_some_and(|hit| hit.distance <= distance) {
+                continue;
+            }
+
+            let position = origin + direction * distance;
+            closest = Some(VoxelHit {
+                volume_id,
+                point_index,
+                distance,
+                position,
+                normal: (position - point.position).normalize_or_zero(),
+            });
+        }
+    }
+
+    closest
+}
@@ -0,0 +1,244 @@
+use crate::voxel::VoxelStorage;
+
+/// Settings for the per-voxel global illumination.
+/// Every frame a budget of voxels gathers the light bouncing off other voxels, so lighting converges over multiple frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GiSettings {
+    pub enabled: bool,
+    /// How many voxels are updated per frame. Higher values converge faster at a higher cost.
+    pub points_per_frame: u32,
+    /// How many bounce rays every updated voxel traces.
+    pub rays_per_point: u32,
+    /// How much of the previous irradiance is kept per update, between 0 and 1. Higher values reduce noise but react slower.
+    pub hysteresis: f32,
+    /// Scales the indirect light on voxels and meshes.
+    pub strength: f32,
+}
+
+impl Default for GiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            points_per_frame: 4096,
+            rays_per_point: 4,
+            hysteresis: 0.9,
+            strength: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GiUniform {
+    cursor: u32,
+    count: u32,
+    rays_per_point: u32,
+    frame: u32,
+    hysteresis: f32,
+    _padding: [u32; 3],
+}
+
+// GiUpdate in gi.wgsl
+const UPDATE_SIZE: wgpu::BufferAddress = 48;
+
+/// The bind group layouts used for tracing and applying the irradiance updates.
+pub(crate) struct GiLayouts {
+    pub trace: wgpu::BindGroupLayout,
+    pub apply: wgpu::BindGroupLayout,
+}
+
+impl GiLayouts {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let trace = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("gi_trace_bind_group_layout"),
+        });
+        let apply = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(8, wgpu::BufferBindingType::Uniform),
+                buffer_entry(9, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(10, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("gi_apply_bind_group_layout"),
+        });
+
+        Self { trace, apply }
+    }
+}
+
+/// Updates the irradiance of the voxels in [`VoxelStorage`], a slice of the voxels per frame.
+pub(crate) struct VoxelGi {
+    pub settings: GiSettings,
+    uniform_buffer: wgpu::Buffer,
+    updates: wgpu::Buffer,
+    update_capacity: u32,
+    trace_bind_group: wgpu::BindGroup,
+    apply_bind_group: wgpu::BindGroup,
+    cursor: u32,
+    frame: u32,
+    count: u32,
+}
+
+impl VoxelGi {
+    pub fn new(device: &wgpu::Device, layouts: &GiLayouts, voxel_storage: &VoxelStorage) -> Self {
+        let settings = GiSettings::default();
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GI Uniform Buffer"),
+            size: std::mem::size_of::<GiUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let update_capacity = settings.points_per_frame.max(1);
+        let updates = create_update_buffer(device, update_capacity);
+        let (trace_bind_group, apply_bind_group) =
+            create_bind_groups(device, layouts, &uniform_buffer, &updates, voxel_storage);
+
+        Self {
+            settings,
+            uniform_buffer,
+            updates,
+            update_capacity,
+            trace_bind_group,
+            apply_bind_group,
+            cursor: 0,
+            frame: 0,
+            count: 0,
+        }
+    }
+
+    /// Picks the voxels updated this frame. `voxels_recreated` has to be `true` if the voxel buffers were recreated.
+    /// Returns `false` if there is nothing to update.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layouts: &GiLayouts,
+        voxel_storage: &VoxelStorage,
+        voxels_recreated: bool,
+    ) -> bool {
+        let point_count = voxel_storage.point_count();
+        if !self.settings.enabled || point_count == 0 {
+            return false;
+        }
+
+        let budget = self.settings.points_per_frame.max(1);
+        let recreated = budget > self.update_capacity;
+        if recreated {
+            self.update_capacity = budget;
+            self.updates = create_update_buffer(device, budget);
+        }
+        if recreated || voxels_recreated {
+            (self.trace_bind_group, self.apply_bind_group) = create_bind_groups(
+                device,
+                layouts,
+                &self.uniform_buffer,
+                &self.updates,
+                voxel_storage,
+            );
+        }
+
+        self.cursor %= point_count;
+        self.count = budget.min(point_count);
+        let uniform = GiUniform {
+            cursor: self.cursor,
+            count: self.count,
+            rays_per_point: self.settings.rays_per_point,
+            frame: self.frame,
+            hysteresis: self.settings.hysteresis,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        self.cursor = (self.cursor + self.count) % point_count;
+        self.frame = self.frame.wrapping_add(1);
+        true
+    }
+
+    /// Traces the voxels picked by `prepare()` and writes their new irradiance.
+    pub fn dispatch<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        voxel_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        trace_pipeline: &'a wgpu::ComputePipeline,
+        apply_pipeline: &'a wgpu::ComputePipeline,
+    ) {
+        let workgroups = self.count.div_ceil(64);
+
+        compute_pass.set_pipeline(trace_pipeline);
+        compute_pass.set_bind_group(0, voxel_bind_group, &[]);
+        compute_pass.set_bind_group(1, light_bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.trace_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups, 1, 1);
+
+        compute_pass.set_pipeline(apply_pipeline);
+        compute_pass.set_bind_group(0, &self.apply_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups, 1, 1);
+    }
+}
+
+fn create_update_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GI Update Buffer"),
+        size: capacity as wgpu::BufferAddress * UPDATE_SIZE,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layouts: &GiLayouts,
+    uniform_buffer: &wgpu::Buffer,
+    updates: &wgpu::Buffer,
+    voxel_storage: &VoxelStorage,
+) -> (wgpu::BindGroup, wgpu::BindGroup) {
+    let trace = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layouts.trace,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: updates.as_entire_binding(),
+            },
+        ],
+        label: Some("gi_trace_bind_group"),
+    });
+    let apply = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layouts.apply,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: updates.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: voxel_storage.irradiance().as_entire_binding(),
+            },
+        ],
+        label: Some("gi_apply_bind_group"),
+    });
+
+    (trace, apply)
+}
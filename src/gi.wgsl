@@ -0,0 +1,128 @@
+#include "voxel.wgsl"
+
+// Compute shader
+
+@group(0) @binding(0)
+var<uniform> voxels: Voxels;
+@group(0) @binding(1)
+var<storage, read> voxel_points: array<VoxelPoint>;
+@group(0) @binding(2)
+var<storage, read> voxel_materials: array<VoxelMaterial>;
+@group(0) @binding(3)
+var<storage, read> cell_offsets: array<u32>;
+@group(0) @binding(4)
+var<storage, read> cell_ends: array<u32>;
+@group(0) @binding(5)
+var<storage, read> cell_indices: array<u32>;
+@group(0) @binding(6)
+var<storage, read> voxel_irradiance: array<VoxelIrradiance>;
+
+#include "light_uniform.wgsl"
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+struct Gi {
+    // The first point updated this frame, the following `count` points are updated as well
+    cursor: u32,
+    count: u32,
+    rays_per_point: u32,
+    frame: u32,
+    hysteresis: f32,
+}
+@group(2) @binding(0)
+var<uniform> gi: Gi;
+
+struct GiUpdate {
+    irradiance: VoxelIrradiance,
+    // 0xffffffff for empty slots
+    index: u32,
+}
+@group(2) @binding(1)
+var<storage, read_write> updates: array<GiUpdate>;
+
+// The irradiance can't be written while it is bound for reading, so the updates are applied in a second pass
+@group(0) @binding(8)
+var<uniform> apply_gi: Gi;
+@group(0) @binding(9)
+var<storage, read> apply_updates: array<GiUpdate>;
+@group(0) @binding(10)
+var<storage, read_write> irradiance_out: array<VoxelIrradiance>;
+
+fn hash(value: u32) -> u32 {
+    // PCG
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed) / 4294967295.0;
+}
+
+fn random_direction(seed: ptr<function, u32>) -> vec3<f32> {
+    let z = random(seed) * 2.0 - 1.0;
+    let angle = random(seed) * 6.2831853;
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    return vec3<f32>(r * cos(angle), r * sin(angle), z);
+}
+
+@compute @workgroup_size(64)
+fn cs_trace(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= gi.count {
+        return;
+    }
+    let index = (gi.cursor + id.x) % voxels.point_count;
+    let point = voxel_points[index];
+    if point.radius < 0.0 {
+        updates[id.x].index = 0xffffffffu;
+        return;
+    }
+    let material = voxel_materials[point.material];
+
+    // Rays start just outside the blended surface of the voxel
+    let offset = point.radius + voxels.blend_radius * 0.25 + VOXEL_HIT_DISTANCE * 4.0;
+    let ray_length = voxels.cell_size * 8.0;
+
+    // Direct light, averaged over the sphere of the voxel
+    let to_light = light.position - point.position;
+    let light_dir = normalize(to_light);
+    var direct = vec3<f32>(0.0);
+    if voxel_march(point.position + light_dir * offset, light_dir, length(to_light) - offset) < 0.0 {
+        direct = light.color * 0.25;
+    }
+
+    // Bounce light gathered from the radiance of the voxels the rays hit
+    var seed = hash(index ^ hash(gi.frame));
+    var indirect = vec3<f32>(0.0);
+    for (var i = 0u; i < gi.rays_per_point; i++) {
+        let direction = random_direction(&seed);
+        let origin = point.position + direction * offset;
+        let t = voxel_march(origin, direction, ray_length);
+        if t >= 0.0 {
+            indirect += voxel_gi(origin + direction * t).radiance;
+        }
+    }
+    indirect /= f32(max(gi.rays_per_point, 1u));
+
+    // Voxels that were never updated take the new value directly
+    let previous = voxel_irradiance[index];
+    let samples = min(previous.samples + 1.0, 1.0 / max(1.0 - gi.hysteresis, 0.0001));
+    let irradiance = mix(previous.indirect, indirect, 1.0 / samples);
+
+    updates[id.x] = GiUpdate(
+        VoxelIrradiance(irradiance, samples, material.color * (direct + irradiance + material.emission)),
+        index,
+    );
+}
+
+@compute @workgroup_size(64)
+fn cs_apply(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= apply_gi.count {
+        return;
+    }
+    let update = apply_updates[id.x];
+    if update.index != 0xffffffffu {
+        irradiance_out[update.index] = update.irradiance;
+    }
+}
@@ -0,0 +1,206 @@
+// Compute shader
+
+#include "voxel_types.wgsl"
+
+@group(0) @binding(0)
+var<uniform> voxels: Voxels;
+@group(0) @binding(1)
+var<storage, read> voxel_points: array<VoxelPoint>;
+
+// Every cell lists the points from cell_offsets[cell] up to cell_ends[cell] in cell_indices
+@group(0) @binding(2)
+var<storage, read_write> cell_offsets: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> cell_ends: array<atomic<u32>>;
+@group(0) @binding(4)
+var<storage, read_write> cell_indices: array<u32>;
+
+// The box of cells that is rebuilt and the slots of the points that can overlap it.
+// After the grid was fitted to the points again this is the whole grid with all points.
+struct GridUpdate {
+    cell_min: vec3<u32>,
+    point_offset: u32,
+    cell_dims: vec3<u32>,
+    point_count: u32,
+}
+
+@group(0) @binding(5)
+var<uniform> grid_update: GridUpdate;
+
+const SCAN_SIZE: u32 = 256u;
+// Enough blocks for the largest grid of 64x64x64 cells
+const MAX_SCAN_BLOCKS: u32 = 1024u;
+
+struct GridScan {
+    // The rebuilt cells list their points after this index, the lists of the other cells stay where they are
+    index_end: u32,
+    block_sums: array<u32, MAX_SCAN_BLOCKS>,
+}
+
+@group(0) @binding(6)
+var<storage, read_write> grid_scan: GridScan;
+
+struct CellRange {
+    min: vec3<u32>,
+    max: vec3<u32>,
+}
+
+// The cells overlapped by the bounding box of the point's radius plus the grid margin, limited to the rebuilt cells
+fn cell_range(point: VoxelPoint) -> CellRange {
+    let reach = point.radius + voxels.grid_margin;
+    let last_cell = vec3<i32>(voxels.grid_dims) - 1;
+    let cell_min = vec3<i32>(floor((point.position - reach - voxels.grid_origin) / voxels.cell_size));
+    let cell_max = vec3<i32>(floor((point.position + reach - voxels.grid_origin) / voxels.cell_size));
+    return CellRange(
+        max(vec3<u32>(clamp(cell_min, vec3<i32>(0), last_cell)), grid_update.cell_min),
+        min(vec3<u32>(clamp(cell_max, vec3<i32>(0), last_cell)), grid_update.cell_min + grid_update.cell_dims - 1u),
+    );
+}
+
+fn cell_index(cell: vec3<u32>) -> u32 {
+    return cell.x + voxels.grid_dims.x * (cell.y + voxels.grid_dims.y * cell.z);
+}
+
+fn update_cell_count() -> u32 {
+    return grid_update.cell_dims.x * grid_update.cell_dims.y * grid_update.cell_dims.z;
+}
+
+// The index of the i-th rebuilt cell in the whole grid
+fn update_cell_index(i: u32) -> u32 {
+    let dims = grid_update.cell_dims;
+    return cell_index(grid_update.cell_min + vec3<u32>(i % dims.x, (i / dims.x) % dims.y, i / (dims.x * dims.y)));
+}
+
+// The slot of the point handled by the invocation, or -1 if there is none
+fn update_point(id: u32) -> i32 {
+    let slot = grid_update.point_offset + id;
+    if id >= grid_update.point_count || slot >= voxels.point_count || voxel_points[slot].radius < 0.0 {
+        return -1;
+    }
+    return i32(slot);
+}
+
+@compute @workgroup_size(64)
+fn cs_clear(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= update_cell_count() {
+        return;
+    }
+    atomicStore(&cell_ends[update_cell_index(id.x)], 0u);
+}
+
+@compute @workgroup_size(64)
+fn cs_count(@builtin(global_invocation_id) id: vec3<u32>) {
+    let slot = update_point(id.x);
+    if slot < 0 {
+        return;
+    }
+
+    let range = cell_range(voxel_points[slot]);
+    for (var z = range.min.z; z <= range.max.z; z++) {
+        for (var y = range.min.y; y <= range.max.y; y++) {
+            for (var x = range.min.x; x <= range.max.x; x++) {
+                atomicAdd(&cell_ends[cell_index(vec3<u32>(x, y, z))], 1u);
+            }
+        }
+    }
+}
+
+var<workgroup> scan_values: array<u32, SCAN_SIZE>;
+
+// The exclusive prefix sum of the values of all invocations in the workgroup, has to be called from uniform control flow
+fn workgroup_scan(index: u32, value: u32) -> u32 {
+    scan_values[index] = value;
+    workgroupBarrier();
+    for (var stride = 1u; stride < SCAN_SIZE; stride *= 2u) {
+        var sum = scan_values[index];
+        if index >= stride {
+            sum += scan_values[index - stride];
+        }
+        workgroupBarrier();
+        scan_values[index] = sum;
+        workgroupBarrier();
+    }
+    return scan_values[index] - value;
+}
+
+// The counts of the rebuilt cells are turned into offsets in three passes. This one scans blocks of SCAN_SIZE cells,
+// cs_scan_blocks then offsets every block by the blocks before it and cs_scan_apply adds the block offsets to the cells.
+// Afterwards both cell_offsets and cell_ends hold the offsets, cs_fill advances cell_ends to the actual ends.
+@compute @workgroup_size(256)
+fn cs_scan(
+    @builtin(local_invocation_index) index: u32,
+    @builtin(workgroup_id) block: vec3<u32>,
+) {
+    let i = block.x * SCAN_SIZE + index;
+    let in_update = i < update_cell_count();
+    var count = 0u;
+    if in_update {
+        count = atomicLoad(&cell_ends[update_cell_index(i)]);
+    }
+
+    let offset = workgroup_scan(index, count);
+    if in_update {
+        cell_offsets[update_cell_index(i)] = offset;
+    }
+    if index == SCAN_SIZE - 1u {
+        grid_scan.block_sums[block.x] = offset + count;
+    }
+}
+
+// Runs as a single workgroup, every invocation handles MAX_SCAN_BLOCKS / SCAN_SIZE consecutive blocks
+@compute @workgroup_size(256)
+fn cs_scan_blocks(@builtin(local_invocation_index) index: u32) {
+    let block_count = (update_cell_count() + SCAN_SIZE - 1u) / SCAN_SIZE;
+    let blocks_per_invocation = MAX_SCAN_BLOCKS / SCAN_SIZE;
+    let first_block = min(index * blocks_per_invocation, block_count);
+    let last_block = min(first_block + blocks_per_invocation, block_count);
+    let index_end = grid_scan.index_end;
+    // Every invocation has read the end before the last one moves it
+    storageBarrier();
+
+    var sum = 0u;
+    for (var i = first_block; i < last_block; i++) {
+        sum += grid_scan.block_sums[i];
+    }
+    var offset = index_end + workgroup_scan(index, sum);
+    for (var i = first_block; i < last_block; i++) {
+        let block_sum = grid_scan.block_sums[i];
+        grid_scan.block_sums[i] = offset;
+        offset += block_sum;
+    }
+    if index == SCAN_SIZE - 1u {
+        grid_scan.index_end = offset;
+    }
+}
+
+@compute @workgroup_size(256)
+fn cs_scan_apply(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= update_cell_count() {
+        return;
+    }
+
+    let cell = update_cell_index(id.x);
+    let offset = cell_offsets[cell] + grid_scan.block_sums[id.x / SCAN_SIZE];
+    cell_offsets[cell] = offset;
+    atomicStore(&cell_ends[cell], offset);
+}
+
+@compute @workgroup_size(64)
+fn cs_fill(@builtin(global_invocation_id) id: vec3<u32>) {
+    let slot = update_point(id.x);
+    if slot < 0 {
+        return;
+    }
+
+    let range = cell_range(voxel_points[slot]);
+    for (var z = range.min.z; z <= range.max.z; z++) {
+        for (var y = range.min.y; y <= range.max.y; y++) {
+            for (var x = range.min.x; x <= range.max.x; x++) {
+                let index = atomicAdd(&cell_ends[cell_index(vec3<u32>(x, y, z))], 1u);
+                if index < voxels.index_capacity {
+                    cell_indices[index] = u32(slot);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use crate::texture;
+
+/// The bind group layouts used for building and reading a [`HiZPyramid`].
+pub(crate) struct HiZLayouts {
+    pub copy: wgpu::BindGroupLayout,
+    pub downsample: wgpu::BindGroupLayout,
+    pub read: wgpu::BindGroupLayout,
+}
+
+impl HiZLayouts {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: HiZPyramid::FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+
+        let copy = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Depth),
+                storage_entry(1),
+            ],
+            label: Some("hiz_copy_bind_group_layout"),
+        });
+        let downsample = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(2, wgpu::TextureSampleType::Float { filterable: false }),
+                storage_entry(3),
+            ],
+            label: Some("hiz_downsample_bind_group_layout"),
+        });
+        let read = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(
+                0,
+                wgpu::TextureSampleType::Float { filterable: false },
+            )],
+            label: Some("hiz_read_bind_group_layout"),
+        });
+
+        Self {
+            copy,
+            downsample,
+            read,
+        }
+    }
+}
+
+/// A mip chain of the depth texture where every texel holds the furthest depth of the texels it covers.
+/// It is built after rendering and used in the next frame to cull instances that were hidden behind others.
+pub(crate) struct HiZPyramid {
+    pub mip_count: u32,
+    copy_bind_group: wgpu::BindGroup,
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    mip_sizes: Vec<(u32, u32)>,
+    /// Binds the whole pyramid for reading.
+    pub bind_group: wgpu::BindGroup,
+    /// Whether the pyramid holds the depth of a rendered frame.
+    pub built: bool,
+}
+
+impl HiZPyramid {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+    pub fn new(
+        device: &wgpu::Device,
+        layouts: &HiZLayouts,
+        depth_texture: &texture::Texture,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let mip_count = config.width.max(config.height).max(1).ilog2() + 1;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hiz_texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let mip_views = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mip_sizes = (0..mip_count)
+            .map(|mip| ((config.width >> mip).max(1), (config.height >> mip).max(1)))
+            .collect();
+
+        let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.copy,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&mip_views[0]),
+                },
+            ],
+            label: Some("hiz_copy_bind_group"),
+        });
+        let downsample_bind_groups = mip_views
+            .windows(2)
+            .map(|views| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layouts.downsample,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&views[0]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(&views[1]),
+                        },
+                    ],
+                    label: Some("hiz_downsample_bind_group"),
+                })
+            })
+            .collect();
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.read,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+            label: Some("hiz_bind_group"),
+        });
+
+        Self {
+            mip_count,
+            copy_bind_group,
+            downsample_bind_groups,
+            mip_sizes,
+            bind_group,
+            built: false,
+        }
+    }
+
+    /// Copies the depth texture into the first mip and reduces it down to the last one.
+    pub fn build<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        copy_pipeline: &'a wgpu::ComputePipeline,
+        downsample_pipeline: &'a wgpu::ComputePipeline,
+    ) {
+        let workgroups = |(width, height): (u32, u32)| (width.div_ceil(8), height.div_ceil(8));
+
+        compute_pass.set_pipeline(copy_pipeline);
+        compute_pass.set_bind_group(0, &self.copy_bind_group, &[]);
+        let (x, y) = workgroups(self.mip_sizes[0]);
+        compute_pass.dispatch_workgroups(x, y, 1);
+
+        compute_pass.set_pipeline(downsample_pipeline);
+        for (bind_group, size) in self.downsample_bind_groups.iter().zip(&self.mip_sizes[1..]) {
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            let (x, y) = workgroups(*size);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+    }
+}
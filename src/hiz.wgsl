@@ -0,0 +1,49 @@
+// Compute shader
+// Builds a pyramid of the furthest depth, which is used for occlusion culling
+
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+@group(0) @binding(1)
+var hiz_base: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_copy_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(hiz_base);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let value = textureLoad(depth, vec2<i32>(id.xy), 0);
+    textureStore(hiz_base, vec2<i32>(id.xy), vec4<f32>(value, 0.0, 0.0, 0.0));
+}
+
+@group(0) @binding(2)
+var previous_mip: texture_2d<f32>;
+@group(0) @binding(3)
+var next_mip: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(next_mip);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let previous_size = vec2<i32>(textureDimensions(previous_mip));
+    let last = vec2<i32>(size) - 1;
+    // With odd sizes the last texel has to cover three texels of the previous mip
+    let extra = vec2<i32>(
+        select(0, 1, (previous_size.x & 1) == 1 && i32(id.x) == last.x),
+        select(0, 1, (previous_size.y & 1) == 1 && i32(id.y) == last.y),
+    );
+
+    let base = vec2<i32>(id.xy) * 2;
+    var furthest = 0.0;
+    for (var y = 0; y <= 1 + extra.y; y++) {
+        for (var x = 0; x <= 1 + extra.x; x++) {
+            let coords = min(base + vec2<i32>(x, y), previous_size - 1);
+            furthest = max(furthest, textureLoad(previous_mip, coords, 0).r);
+        }
+    }
+    textureStore(next_mip, vec2<i32>(id.xy), vec4<f32>(furthest, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// An asset that was reloaded by `RenderState::reload_changed_assets()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReloadedAsset {
+    /// A texture file, every material using it was rebuilt.
+    Texture(String),
+    /// The id of a model whose `.obj` or `.mtl` file changed.
+    Model(usize),
+    /// A WGSL file, every built-in and material pipeline using it was created again.
+    Shader(String),
+}
+
+/// A changed file that couldn't be reloaded, the previous version stays in use.
+#[derive(Clone, Debug)]
+pub struct ReloadError {
+    pub file_name: String,
+    pub message: String,
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to reload {}: {}", self.file_name, self.message)
+    }
+}
+
+impl std::error::Error for ReloadError {}
+
+/// Detects changed files by polling their modification times.
+#[derive(Default)]
+pub(crate) struct FileWatcher {
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+    last_poll: Option<Instant>,
+}
+
+impl FileWatcher {
+    /// Polling more often only stats every file again without noticing changes any sooner
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Replaces the watched files. Files that weren't watched before don't count as changed.
+    pub fn watch(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        let previous = std::mem::take(&mut self.modified);
+        self.modified = paths
+            .into_iter()
+            .map(|path| {
+                let modified = previous
+                    .get(&path)
+                    .copied()
+                    .unwrap_or_else(|| modified(&path));
+                (path, modified)
+            })
+            .collect();
+    }
+
+    /// The watched files that were modified since the last call. Deleted files are reported once they exist again.
+    /// Returns nothing if the files were already checked within the last [`Self::POLL_INTERVAL`].
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        if self
+            .last_poll
+            .is_some_and(|last_poll| now - last_poll < Self::POLL_INTERVAL)
+        {
+            return vec![];
+        }
+        self.last_poll = Some(now);
+
+        let mut changed = vec![];
+        for (path, last_modified) in &mut self.modified {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                if modified.is_some() {
+                    changed.push(path.clone());
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
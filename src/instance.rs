@@ -1,17 +1,21 @@
 use glam::{Mat3, Mat4, Quat, Vec3};
 
-use crate::model;
+use crate::{buffer::GrowableBuffer, model};
 
+#[derive(Clone, Copy, Debug)]
 pub struct Instance {
     pub position: Vec3,
     pub rotation: Quat,
 }
 
 impl Instance {
+    pub fn model_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.position) * Mat4::from_quat(self.rotation)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let model = Mat4::from_translation(self.position) * Mat4::from_quat(self.rotation);
         InstanceRaw {
-            model: model.to_cols_array_2d(),
+            model: self.model_matrix().to_cols_array_2d(),
             normal: Mat3::from_quat(self.rotation).to_cols_array_2d(),
         }
     }
@@ -77,3 +81,101 @@ impl model::Vertex for InstanceRaw {
         }
     }
 }
+
+/// GPU storage for the [`Instance`]s of a [`Model`](model::Model).
+/// Only visible instances are stored and they are kept packed at the start of the buffer, so they can be drawn as a single range.
+/// Hiding an instance moves the last visible instance into its slot, which means the ids of the instances never change.
+pub(crate) struct InstanceBuffer {
+    raw: GrowableBuffer<InstanceRaw>,
+    // GPU slot of every instance, `None` if the instance is hidden
+    slots: Vec<Option<usize>>,
+    // instance id stored in every GPU slot
+    owners: Vec<usize>,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, instances: &[Instance]) -> Self {
+        let raw = GrowableBuffer::new(
+            device,
+            "Instance Buffer",
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            instances.iter().map(Instance::to_raw).collect(),
+        );
+
+        Self {
+            raw,
+            slots: (0..instances.len()).map(Some).collect(),
+            owners: (0..instances.len()).collect(),
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        self.raw.buffer()
+    }
+
+    /// The ids of the visible instances in the order they are stored on the GPU.
+    pub fn instance_ids(&self) -> &[usize] {
+        &self.owners
+    }
+
+    /// The number of visible instances, which are stored in `0..visible_count()`.
+    pub fn visible_count(&self) -> u32 {
+        self.owners.len() as u32
+    }
+
+    pub fn is_visible(&self, instance_id: usize) -> bool {
+        self.slots[instance_id].is_some()
+    }
+
+    pub fn push(&mut self, instance: &Instance) {
+        let slot = self.raw.push(instance.to_raw());
+        self.slots.push(Some(slot));
+        self.owners.push(self.slots.len() - 1);
+    }
+
+    pub fn set(&mut self, instance_id: usize, instance: &Instance) {
+        if let Some(slot) = self.slots[instance_id] {
+            self.raw.set(slot, instance.to_raw());
+        }
+    }
+
+    /// Removes an instance, all ids larger than `instance_id` are shifted down by one.
+    pub fn remove(&mut self, instance_id: usize) {
+        self.hide(instance_id);
+        self.slots.remove(instance_id);
+        for owner in &mut self.owners {
+            if *owner > instance_id {
+                *owner -= 1;
+            }
+        }
+    }
+
+    /// Replaces all instances, which also makes all of them visible.
+    pub fn replace(&mut self, instances: &[Instance]) {
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        self.raw.replace(&instance_data);
+        self.slots = (0..instances.len()).map(Some).collect();
+        self.owners = (0..instances.len()).collect();
+    }
+
+    pub fn show(&mut self, instance_id: usize, instance: &Instance) {
+        if self.slots[instance_id].is_none() {
+            self.slots[instance_id] = Some(self.raw.push(instance.to_raw()));
+            self.owners.push(instance_id);
+        }
+    }
+
+    pub fn hide(&mut self, instance_id: usize) {
+        if let Some(slot) = self.slots[instance_id].take() {
+            self.raw.swap_remove(slot);
+            self.owners.swap_remove(slot);
+            if slot < self.owners.len() {
+                self.slots[self.owners[slot]] = Some(slot);
+            }
+        }
+    }
+
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.raw.flush(device, queue)
+    }
+}
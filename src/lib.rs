@@ -1,72 +1,123 @@
-use camera::{Camera, CameraUniform};
-use instance::{Instance, InstanceRaw};
+use std::{collections::HashMap, ops::Range, path::PathBuf, sync::Arc};
+
+use camera::{Camera, CameraUniform, StereoCamera};
+use culling::{cull_model, CullTarget, CullingMode, CullingStats, Frustum};
+use edit::{Brush, EditLog, VoxelHit};
+use gi::{GiLayouts, GiSettings, VoxelGi};
+use glam::Vec2;
+use hiz::{HiZLayouts, HiZPyramid};
+use hot_reload::{FileWatcher, ReloadError, ReloadedAsset};
+use instance::{Instance, InstanceBuffer, InstanceRaw};
 use light::LightUniform;
+use material::{MaterialAssignment, MaterialPipeline};
 use model::{DrawLight, DrawModel, Model, Vertex};
-use resources::load_model;
-use texture::Texture;
+use pipeline::{Pipeline, PipelineRecipe, PipelineSlot};
+use reflection::ReflectionSettings;
+use reprojection::{Reprojection, ReprojectionLayouts, ReprojectionSettings};
+use resources::{load_model, load_vox, voxelize_model, AssetCache};
+use shader::{ShaderError, ShaderKey, ShaderLoader};
+use stereo::{Stereo, StereoLayout};
+use texture::{SamplerCache, SamplerSettings, Texture, TextureFiltering, TextureSlot};
+use voxel::{SmoothMin, VoxelStorage, VoxelVolume};
 use wgpu::util::DeviceExt;
 use winit::{dpi::PhysicalSize, window::Window};
 
+mod buffer;
 pub mod camera;
+pub mod culling;
+pub mod edit;
+pub mod gi;
+mod hiz;
+pub mod hot_reload;
 pub mod instance;
 pub mod light;
-mod model;
+mod material;
+// Public for creating materials from HDR, cubemap and array textures with `Material::new()`
+pub mod model;
+mod pipeline;
+pub mod reflection;
+pub mod reprojection;
 mod resources;
-mod texture;
+pub mod shader;
+pub mod stereo;
+// Public for the sampler settings of material textures, the cache sharing the samplers stays internal
+pub mod texture;
+pub mod voxel;
+
+/// The instance ranges to draw per model and mesh of a camera.
+type CameraDraws = Vec<Vec<Vec<Range<u32>>>>;
+
+/// The work `RenderState::prepare_scene()` leaves for encoding.
+struct PreparedScene {
+    draws: Vec<Option<CameraDraws>>,
+    build_voxel_grid: bool,
+    update_gi: bool,
+}
 
 /// This holds all the required information for rendering the scene.
 pub struct RenderState {
     // wgpu context
-    surface: wgpu::Surface,
+    surface: Option<wgpu::Surface>,
+    headless_texture: Option<wgpu::Texture>,
     surface_config: wgpu::SurfaceConfiguration,
     device: wgpu::Device,
     queue: wgpu::Queue,
     // scene data
     models: Vec<Option<Model>>,
-    instance_buffers: Vec<Option<wgpu::Buffer>>,
-    recreate_instance_buffers: Vec<usize>,
-    depth_texture: Texture,
+    instance_buffers: Vec<Option<InstanceBuffer>>,
+    voxel_storage: VoxelStorage,
+    voxel_edits: EditLog,
+    voxel_bind_group_layout: wgpu::BindGroupLayout,
+    voxel_grid_bind_group_layout: wgpu::BindGroupLayout,
+    gi_layouts: GiLayouts,
+    voxel_gi: VoxelGi,
     texture_bind_group_layout: wgpu::BindGroupLayout,
+    sampler_settings: SamplerSettings,
+    sampler_cache: SamplerCache,
+    asset_cache: AssetCache,
+    file_watcher: Option<FileWatcher>,
     // cameras
     cameras: Vec<Option<Camera>>,
+    camera_lens_shifts: Vec<Vec2>,
     camera_uniforms: Vec<Option<CameraUniform>>,
     camera_buffers: Vec<Option<wgpu::Buffer>>,
     camera_bind_groups: Vec<Option<wgpu::BindGroup>>,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    // Every camera has its own depth, so overlapping viewports neither depth test nor occlusion cull against each other
+    depth_textures: Vec<Option<Texture>>,
+    // culling
+    culling_mode: CullingMode,
+    culling_stats: Vec<Option<CullingStats>>,
+    cull_bind_group_layout: wgpu::BindGroupLayout,
+    cull_targets: Vec<Vec<Option<CullTarget>>>,
+    occlusion_culling: bool,
+    hiz_layouts: HiZLayouts,
+    hiz_pyramids: Vec<Option<HiZPyramid>>,
+    // reprojection
+    reprojection_layouts: ReprojectionLayouts,
+    reprojection: Reprojection,
+    // stereo
+    stereo: Option<Stereo>,
+    light_bind_group_layout: wgpu::BindGroupLayout,
     light_bind_group: wgpu::BindGroup,
     // pipelines
     render_pipelines: Vec<wgpu::RenderPipeline>,
-    _compute_pipelines: Vec<wgpu::ComputePipeline>,
+    compute_pipelines: Vec<wgpu::ComputePipeline>,
+    pipeline_recipes: Vec<PipelineRecipe>,
+    shader_loader: ShaderLoader,
+    // material pipelines
+    texture_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    material_pipelines: Vec<MaterialPipeline>,
+    material_assignments: HashMap<usize, MaterialAssignment>,
 }
 
 impl RenderState {
     pub async fn new(window: &Window) -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::VULKAN,
-            ..Default::default()
-        });
+        let instance = create_instance();
 
         let surface = unsafe { instance.create_surface(window) }.unwrap();
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptionsBase {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (adapter, device, queue) = request_device(&instance, Some(&surface)).await.unwrap();
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -75,8 +126,11 @@ impl RenderState {
             .copied()
             .find(|f| f.is_srgb())
             .unwrap_or(surface_caps.formats[0]);
+        // Copying from the frame is needed for the layered stereo output
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
         let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage,
             format: surface_format,
             width: window.inner_size().width,
             height: window.inner_size().height,
@@ -86,13 +140,119 @@ impl RenderState {
         };
         surface.configure(&device, &surface_config);
 
+        Self::with_target(device, queue, Some(surface), surface_config)
+    }
+
+    /// Creates a [`RenderState`] without a window, frames are rendered into a texture of the given size.
+    /// Useful for tests and offline rendering, see `read_eye_images()`. Fails if there is no adapter to render with.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = create_instance();
+
+        let (_, device, queue) = request_device(&instance, None).await?;
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        Ok(Self::with_target(device, queue, None, surface_config))
+    }
+
+    /// Sets up everything but the render target, which is either the `surface` or a headless texture if there is none.
+    fn with_target(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface>,
+        surface_config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let headless_texture = surface
+            .is_none()
+            .then(|| create_headless_texture(&device, &surface_config));
+
         let models = vec![];
 
         let instance_buffers = vec![];
-        let recreate_instance_buffers = vec![];
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &surface_config, "depth_texture");
+        let voxel_uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::all(),
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let voxel_storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::all(),
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let voxel_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    voxel_uniform_entry,
+                    voxel_storage_entry(1, true),
+                    voxel_storage_entry(2, true),
+                    voxel_storage_entry(3, true),
+                    voxel_storage_entry(4, true),
+                    voxel_storage_entry(5, true),
+                    voxel_storage_entry(6, true),
+                ],
+                label: Some("voxel_bind_group_layout"),
+            });
+        // Used by the compute passes building the voxel grid
+        let voxel_grid_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_uniform_entry
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_storage_entry(1, true)
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_storage_entry(2, false)
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_storage_entry(3, false)
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_storage_entry(4, false)
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_uniform_entry
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ..voxel_storage_entry(6, false)
+                    },
+                ],
+                label: Some("voxel_grid_bind_group_layout"),
+            });
+
+        let voxel_storage = VoxelStorage::new(
+            &device,
+            &voxel_bind_group_layout,
+            &voxel_grid_bind_group_layout,
+        );
 
         let cameras = vec![];
 
@@ -117,6 +277,65 @@ impl RenderState {
 
         let camera_bind_groups = vec![];
 
+        let culling_stats = vec![];
+
+        let cull_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("cull_bind_group_layout"),
+            });
+
         let light_uniform = LightUniform {
             position: [2.0, 2.0, 2.0],
             _padding: 0,
@@ -134,7 +353,9 @@ impl RenderState {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::VERTEX
+                        | wgpu::ShaderStages::FRAGMENT
+                        | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -154,42 +375,53 @@ impl RenderState {
             label: None,
         });
 
+        let texture_layout_entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 3,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
+                entries: &texture_layout_entries,
                 label: Some("texture_bind_group_layout"),
             });
 
@@ -200,129 +432,510 @@ impl RenderState {
                     &texture_bind_group_layout,
                     &camera_bind_group_layout,
                     &light_bind_group_layout,
+                    &voxel_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                surface_config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let hiz_layouts = HiZLayouts::new(&device);
+
+        let gi_layouts = GiLayouts::new(&device);
+        let voxel_gi = VoxelGi::new(&device, &gi_layouts, &voxel_storage);
+
+        let reprojection_layouts = ReprojectionLayouts::new(&device);
+        let reprojection = Reprojection::new(&device, &surface_config);
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+        let pipeline_layout = |label, bind_group_layouts: &[&wgpu::BindGroupLayout]| {
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts,
                 push_constant_ranges: &[],
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                surface_config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
+            })
         };
+        let color_format = surface_config.format;
+        let depth_format = Some(texture::Texture::DEPTH_FORMAT);
+        let mut pipeline_recipes = vec![
+            PipelineRecipe::render(
+                "Normal Shader",
+                0,
+                ShaderKey::new("shader.wgsl", ["NORMAL_MAP"]),
+                render_pipeline_layout,
+                color_format,
+                depth_format,
+                vec![model::ModelVertex::desc(), InstanceRaw::desc()],
+            ),
+            PipelineRecipe::render(
+                "Light Shader",
+                1,
+                "light.wgsl".into(),
+                pipeline_layout(
+                    "Light Pipeline Layout",
+                    &[&camera_bind_group_layout, &light_bind_group_layout],
+                ),
+                color_format,
+                depth_format,
+                vec![model::ModelVertex::desc()],
+            ),
+            PipelineRecipe::render(
+                "Raymarch Shader",
+                2,
+                "raymarch.wgsl".into(),
+                pipeline_layout(
+                    "Raymarch Pipeline Layout",
+                    &[
+                        &camera_bind_group_layout,
+                        &light_bind_group_layout,
+                        &voxel_bind_group_layout,
+                    ],
+                ),
+                color_format,
+                depth_format,
+                vec![],
+            ),
+            PipelineRecipe::render(
+                "Reprojection Shader",
+                3,
+                "reproject.wgsl".into(),
+                pipeline_layout(
+                    "Reprojection Pipeline Layout",
+                    &[&reprojection_layouts.camera, &reprojection_layouts.source],
+                ),
+                color_format,
+                None,
+                vec![],
+            ),
+            PipelineRecipe::compute(
+                "Cull Pipeline",
+                0,
+                "cull.wgsl".into(),
+                pipeline_layout(
+                    "Cull Pipeline Layout",
+                    &[&cull_bind_group_layout, &hiz_layouts.read],
+                ),
+                "cs_main",
+            ),
+            PipelineRecipe::compute(
+                "Hi-Z Pipeline",
+                1,
+                "hiz.wgsl".into(),
+                pipeline_layout("Hi-Z Pipeline Layout", &[&hiz_layouts.copy]),
+                "cs_copy_depth",
+            ),
+            PipelineRecipe::compute(
+                "Hi-Z Pipeline",
+                2,
+                "hiz.wgsl".into(),
+                pipeline_layout("Hi-Z Pipeline Layout", &[&hiz_layouts.downsample]),
+                "cs_downsample",
+            ),
+        ];
+        for (index, entry_point) in [
+            "cs_clear",
+            "cs_count",
+            "cs_scan",
+            "cs_scan_blocks",
+            "cs_scan_apply",
+            "cs_fill",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            pipeline_recipes.push(PipelineRecipe::compute(
+                "Voxel Grid Pipeline",
+                3 + index,
+                "grid.wgsl".into(),
+                pipeline_layout(
+                    "Voxel Grid Pipeline Layout",
+                    &[&voxel_grid_bind_group_layout],
+                ),
+                entry_point,
+            ));
+        }
+        pipeline_recipes.extend([
+            PipelineRecipe::compute(
+                "GI Pipeline",
+                9,
+                "gi.wgsl".into(),
+                pipeline_layout(
+                    "GI Pipeline Layout",
+                    &[
+                        &voxel_bind_group_layout,
+                        &light_bind_group_layout,
+                        &gi_layouts.trace,
+                    ],
+                ),
+                "cs_trace",
+            ),
+            PipelineRecipe::compute(
+                "GI Pipeline",
+                10,
+                "gi.wgsl".into(),
+                pipeline_layout("GI Pipeline Layout", &[&gi_layouts.apply]),
+                "cs_apply",
+            ),
+        ]);
 
-        let render_pipelines = vec![render_pipeline, light_render_pipeline];
-        let _compute_pipelines = vec![];
+        let mut render_pipelines = vec![];
+        let mut compute_pipelines = vec![];
+        let mut shader_loader = ShaderLoader::default();
+        for recipe in &mut pipeline_recipes {
+            let shader = shader_loader.module(&device, &recipe.shader).unwrap();
+            recipe.shader_files = shader_loader.files(&recipe.shader).unwrap().to_vec();
+            match recipe.create(&device, &shader) {
+                Pipeline::Render(render_pipeline) => render_pipelines.push(render_pipeline),
+                Pipeline::Compute(compute_pipeline) => compute_pipelines.push(compute_pipeline),
+            }
+        }
 
         Self {
             surface,
+            headless_texture,
             surface_config,
             device,
             queue,
             models,
             instance_buffers,
-            recreate_instance_buffers,
-            depth_texture,
+            voxel_storage,
+            voxel_edits: EditLog::default(),
+            voxel_bind_group_layout,
+            voxel_grid_bind_group_layout,
+            gi_layouts,
+            voxel_gi,
             texture_bind_group_layout,
+            sampler_settings: SamplerSettings::default(),
+            sampler_cache: SamplerCache::default(),
+            asset_cache: AssetCache::default(),
+            file_watcher: None,
             cameras,
+            camera_lens_shifts: vec![],
             camera_uniforms,
             camera_buffers,
             camera_bind_groups,
+            camera_bind_group_layout,
+            depth_textures: vec![],
+            culling_mode: CullingMode::default(),
+            culling_stats,
+            cull_bind_group_layout,
+            cull_targets: vec![],
+            occlusion_culling: false,
+            hiz_layouts,
+            hiz_pyramids: vec![],
+            reprojection_layouts,
+            reprojection,
+            stereo: None,
+            light_bind_group_layout,
             light_bind_group,
             render_pipelines,
-            _compute_pipelines,
+            compute_pipelines,
+            pipeline_recipes,
+            shader_loader,
+            texture_layout_entries: texture_layout_entries.to_vec(),
+            material_pipelines: vec![],
+            material_assignments: HashMap::new(),
         }
     }
 
+    /// The device everything is rendered with, for custom passes and resources used with the renderer.
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    /// The queue the renderer submits its work to, for uploading to custom resources.
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// The format of the surface, or of the texture when rendering headless, which every pipeline renders to.
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.surface_config.format
+    }
+
+    /// The layout of the material bind groups, group 0 of the built-in model pipeline.
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// The layout of the material bind groups of a material pipeline, which additionally bind its uniform at binding 5.
+    pub fn material_bind_group_layout(&self, pipeline_id: usize) -> Option<&wgpu::BindGroupLayout> {
+        self.material_pipelines
+            .get(pipeline_id)
+            .map(|pipeline| &pipeline.bind_group_layout)
+    }
+
+    /// The layout of the camera bind groups, group 1 of the model pipelines.
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    /// The layout of the light bind group, group 2 of the model pipelines.
+    pub fn light_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.light_bind_group_layout
+    }
+
+    /// The layout of the voxel scene bind group, group 3 of the model pipelines.
+    pub fn voxel_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.voxel_bind_group_layout
+    }
+
+    /// The bind group holding the [`CameraUniform`] of a camera, or [`None`] if it was removed.
+    pub fn camera_bind_group(&self, camera_id: usize) -> Option<&wgpu::BindGroup> {
+        self.camera_bind_groups[camera_id].as_ref()
+    }
+
+    /// The bind group holding the [`LightUniform`], group 2 of the model pipelines.
+    pub fn light_bind_group(&self) -> &wgpu::BindGroup {
+        &self.light_bind_group
+    }
+
     pub fn resize(&mut self, size: PhysicalSize<u32>) {
         if size.width > 0 && size.height > 0 {
             self.surface_config.width = size.width;
             self.surface_config.height = size.height;
-            self.surface.configure(&self.device, &self.surface_config);
-            self.depth_texture = texture::Texture::create_depth_texture(
-                &self.device,
-                &self.surface_config,
-                "depth_texture",
-            );
+            match &self.surface {
+                Some(surface) => surface.configure(&self.device, &self.surface_config),
+                None => {
+                    self.headless_texture =
+                        Some(create_headless_texture(&self.device, &self.surface_config))
+                }
+            }
+            for camera_id in 0..self.cameras.len() {
+                if self.cameras[camera_id].is_some() {
+                    let (depth_texture, hiz_pyramid) = self.create_camera_depth();
+                    self.depth_textures[camera_id] = Some(depth_texture);
+                    self.hiz_pyramids[camera_id] = Some(hiz_pyramid);
+                }
+            }
+            self.reprojection.resize(&self.device, &self.surface_config);
+            if let Some(stereo) = &mut self.stereo {
+                stereo.resize(&self.device, &self.surface_config);
+                // The viewports of the eyes depend on the size
+                self.update_stereo_eyes();
+            }
         }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        if !self.recreate_instance_buffers.is_empty() {
-            for i in &self.recreate_instance_buffers {
-                let model = self.models[*i].as_mut().unwrap();
+        let output = match &self.surface {
+            Some(surface) => Some(surface.get_current_texture()?),
+            None => None,
+        };
+        let view = self
+            .frame_texture(output.as_ref())
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        if self.reprojection.settings.enabled {
+            // The scene is rendered into the reprojection texture at the render rate, every frame reprojects it to the current poses
+            if self.reprojection.needs_render(&self.cameras) {
+                let scene = self.prepare_scene();
+                self.encode_scene(&mut encoder, self.reprojection.color_view(), scene);
+                self.reprojection.store_poses(
+                    &self.device,
+                    &self.reprojection_layouts,
+                    &self.cameras,
+                    &self.camera_lens_shifts,
+                    &self.depth_textures,
+                );
+            }
+            self.reprojection.reproject(
+                &self.queue,
+                &mut encoder,
+                &view,
+                &self.render_pipelines[3],
+                &self.cameras,
+                &self.camera_lens_shifts,
+            );
+        } else {
+            let scene = self.prepare_scene();
+            self.encode_scene(&mut encoder, &view, scene);
+        }
+
+        if let Some(stereo) = &self.stereo {
+            stereo.copy_eyes(&mut encoder, self.frame_texture(output.as_ref()));
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(output) = output {
+            output.present();
+        }
+
+        Ok(())
+    }
+
+    /// The texture the current frame is rendered into.
+    fn frame_texture<'a>(&'a self, output: Option<&'a wgpu::SurfaceTexture>) -> &'a wgpu::Texture {
+        match output {
+            Some(output) => &output.texture,
+            None => self.headless_texture.as_ref().unwrap(),
+        }
+    }
+
+    fn aspect(&self) -> f32 {
+        self.surface_config.width as f32 / self.surface_config.height as f32
+    }
+
+    /// Uploads pending changes and culls the instances of every camera.
+    fn prepare_scene(&mut self) -> PreparedScene {
+        for (model_index, instance_buffer) in self.instance_buffers.iter_mut().enumerate() {
+            if let Some(instance_buffer) = instance_buffer {
+                if instance_buffer.flush(&self.device, &self.queue) {
+                    // The cull targets still reference the old instance buffer
+                    for targets in &mut self.cull_targets {
+                        if let Some(target) = targets.get_mut(model_index) {
+                            *target = None;
+                        }
+                    }
+                }
+            }
+        }
 
-                let instance_data = model
-                    .instances
+        self.voxel_storage.gi_strength = if self.voxel_gi.settings.enabled {
+            self.voxel_gi.settings.strength
+        } else {
+            0.0
+        };
+        let voxels_recreated = self.voxel_storage.flush(
+            &self.device,
+            &self.queue,
+            &self.voxel_bind_group_layout,
+            &self.voxel_grid_bind_group_layout,
+        );
+        let build_voxel_grid = self.voxel_storage.take_grid_build();
+        let update_gi = self.voxel_gi.prepare(
+            &self.device,
+            &self.queue,
+            &self.gi_layouts,
+            &self.voxel_storage,
+            voxels_recreated,
+        );
+
+        let aspect = self.aspect();
+        if self.culling_mode == CullingMode::Gpu {
+            self.prepare_gpu_culling(aspect);
+        }
+
+        // Cull the instances of every model against every camera's frustum, collecting the instance ranges to draw per mesh
+        let mut draws = Vec::with_capacity(self.cameras.len());
+        for (camera_index, camera) in self.cameras.iter().enumerate() {
+            if self.culling_mode == CullingMode::Gpu {
+                self.culling_stats[camera_index] = None;
+                draws.push(None);
+                continue;
+            }
+
+            let camera_draws = camera.as_ref().map(|camera| {
+                let frustum =
+                    Frustum::from_view_projection(&camera.build_shifted_view_projection_matrix(
+                        aspect,
+                        self.camera_lens_shifts[camera_index],
+                    ));
+                let mut stats = CullingStats::default();
+
+                let model_draws = self
+                    .models
                     .iter()
-                    .map(Instance::to_raw)
+                    .zip(&self.instance_buffers)
+                    .map(|(model, instance_buffer)| match (model, instance_buffer) {
+                        (Some(model), Some(instance_buffer)) => {
+                            cull_model(&frustum, model, instance_buffer, &mut stats)
+                        }
+                        _ => vec![],
+                    })
                     .collect::<Vec<_>>();
 
-                self.instance_buffers[*i] = Some(self.device.create_buffer_init(
-                    &wgpu::util::BufferInitDescriptor {
-                        label: Some("Instance Buffer"),
-                        contents: bytemuck::cast_slice(&instance_data),
-                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                    },
-                ));
-            }
+                self.culling_stats[camera_index] = Some(stats);
+                model_draws
+            });
+            draws.push(camera_draws);
         }
 
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
+        for hiz_pyramid in self.hiz_pyramids.iter_mut().flatten() {
+            hiz_pyramid.built = self.culling_mode == CullingMode::Gpu && self.occlusion_culling;
+        }
+
+        PreparedScene {
+            draws,
+            build_voxel_grid,
+            update_gi,
+        }
+    }
+
+    /// Records the compute and render passes of the scene, rendering into `view`.
+    fn encode_scene(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        scene: PreparedScene,
+    ) {
+        let PreparedScene {
+            draws,
+            build_voxel_grid,
+            update_gi,
+        } = scene;
+
+        if build_voxel_grid || update_gi {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Voxel Pass"),
+                timestamp_writes: None,
             });
-        {
+            if build_voxel_grid {
+                self.voxel_storage
+                    .build_grid(&mut compute_pass, &self.compute_pipelines[3..9]);
+            }
+            if update_gi {
+                self.voxel_gi.dispatch(
+                    &mut compute_pass,
+                    self.voxel_storage.bind_group(),
+                    &self.light_bind_group,
+                    &self.compute_pipelines[9],
+                    &self.compute_pipelines[10],
+                );
+            }
+        }
+        if self.culling_mode == CullingMode::Gpu {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Cull Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.compute_pipelines[0]);
+            for (targets, hiz_pyramid) in self.cull_targets.iter().zip(&self.hiz_pyramids) {
+                let Some(hiz_pyramid) = hiz_pyramid else {
+                    continue;
+                };
+                compute_pass.set_bind_group(1, &hiz_pyramid.bind_group, &[]);
+                for target in targets.iter().flatten() {
+                    target.dispatch(&mut compute_pass);
+                }
+            }
+        }
+        // Every camera renders in its own pass with its own depth, the first one clears the frame
+        let mut color_load = wgpu::LoadOp::Clear(wgpu::Color {
+            r: 0.1,
+            g: 0.1,
+            b: 0.1,
+            a: 1.0,
+        });
+        for (camera_index, camera) in self.cameras.iter().enumerate() {
+            let (Some(camera), Some(depth_texture)) = (camera, &self.depth_textures[camera_index])
+            else {
+                continue;
+            };
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
-                        }),
+                        load: color_load,
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: &depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -332,62 +945,377 @@ impl RenderState {
                 timestamp_writes: None,
                 occlusion_query_set: None,
             });
+            color_load = wgpu::LoadOp::Load;
 
-            for (camera_index, camera) in self.cameras.iter().enumerate() {
-                if camera.is_some() {
-                    let viewport = &camera.as_ref().unwrap().viewport;
-
-                    if viewport.is_some() {
-                        render_pass.set_viewport(
-                            viewport.as_ref().unwrap().x,
-                            viewport.as_ref().unwrap().y,
-                            viewport.as_ref().unwrap().w,
-                            viewport.as_ref().unwrap().h,
-                            0.0,
-                            1.0,
-                        );
-                    }
+            let viewport = &camera.viewport;
 
-                    // TODO: *IMPROVEMENTS MUST BE MADE*
-                    for (model_index, model) in self.models.iter().enumerate() {
-                        if model.is_some() {
-                            let model = model.as_ref().unwrap();
-                            render_pass.set_vertex_buffer(
-                                1,
-                                self.instance_buffers[model_index]
-                                    .as_ref()
-                                    .unwrap()
-                                    .slice(..),
-                            );
-                            render_pass.set_pipeline(&self.render_pipelines[1]);
-                            render_pass.draw_light_model(
-                                model,
-                                self.camera_bind_groups[camera_index].as_ref().unwrap(),
-                                &self.light_bind_group,
-                            );
-                            render_pass.set_pipeline(&self.render_pipelines[0]);
-                            render_pass.draw_model_instanced(
-                                model,
-                                0..model.instances.len() as u32,
+            if viewport.is_some() {
+                render_pass.set_viewport(
+                    viewport.as_ref().unwrap().x,
+                    viewport.as_ref().unwrap().y,
+                    viewport.as_ref().unwrap().w,
+                    viewport.as_ref().unwrap().h,
+                    0.0,
+                    1.0,
+                );
+            }
+
+            // TODO: *IMPROVEMENTS MUST BE MADE*
+            for (model_index, model) in self.models.iter().enumerate() {
+                if model.is_some() {
+                    let model = model.as_ref().unwrap();
+                    let cull_target = self
+                        .cull_targets
+                        .get(camera_index)
+                        .and_then(|targets| targets.get(model_index))
+                        .and_then(Option::as_ref);
+                    render_pass.set_pipeline(&self.render_pipelines[1]);
+                    render_pass.draw_light_model(
+                        model,
+                        self.camera_bind_groups[camera_index].as_ref().unwrap(),
+                        &self.light_bind_group,
+                    );
+                    let assignment = self.material_assignments.get(&model_index);
+                    let material = |index: usize| match assignment {
+                        Some(assignment) => &assignment.materials[index],
+                        None => &*model.materials[index],
+                    };
+                    match assignment {
+                        Some(assignment) => render_pass.set_pipeline(
+                            &self.material_pipelines[assignment.pipeline_id].pipeline,
+                        ),
+                        None => render_pass.set_pipeline(&self.render_pipelines[0]),
+                    }
+                    render_pass.set_bind_group(3, self.voxel_storage.bind_group(), &[]);
+                    if let Some(cull_target) = cull_target {
+                        for (mesh_index, mesh) in model.meshes.iter().enumerate() {
+                            render_pass
+                                .set_vertex_buffer(1, cull_target.mesh_instances(mesh_index));
+                            render_pass.draw_mesh_indirect(
+                                mesh,
+                                material(mesh.material),
+                                &cull_target.indirect_buffer,
+                                mesh_index as wgpu::BufferAddress * CullTarget::INDIRECT_SIZE,
                                 self.camera_bind_groups[camera_index].as_ref().unwrap(),
                                 &self.light_bind_group,
                             );
                         }
+                    } else if let Some(camera_draws) = &draws[camera_index] {
+                        render_pass.set_vertex_buffer(
+                            1,
+                            self.instance_buffers[model_index]
+                                .as_ref()
+                                .unwrap()
+                                .buffer()
+                                .slice(..),
+                        );
+                        for (mesh, ranges) in model.meshes.iter().zip(&camera_draws[model_index]) {
+                            for range in ranges {
+                                render_pass.draw_mesh_instanced(
+                                    mesh,
+                                    material(mesh.material),
+                                    range.clone(),
+                                    self.camera_bind_groups[camera_index].as_ref().unwrap(),
+                                    &self.light_bind_group,
+                                );
+                            }
+                        }
                     }
                 }
             }
-        }
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-
-        Ok(())
-    }
-
-    /// This can be used for adding custom shaders using a [`wgpu::RenderPipelineDescriptor`].
-    pub fn add_render_pipeline(&mut self, desc: &wgpu::RenderPipelineDescriptor) {
-        let render_pipeline = self.device.create_render_pipeline(desc);
-        self.render_pipelines.push(render_pipeline);
-    }
+
+            // Raymarch the voxels, which are depth tested against the models
+            if self.voxel_storage.point_count() > 0 {
+                render_pass.set_pipeline(&self.render_pipelines[2]);
+                render_pass.set_bind_group(
+                    0,
+                    self.camera_bind_groups[camera_index].as_ref().unwrap(),
+                    &[],
+                );
+                render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+                render_pass.set_bind_group(2, self.voxel_storage.bind_group(), &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+        if matches!(color_load, wgpu::LoadOp::Clear(_)) {
+            // Without cameras the frame is only cleared
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: color_load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+        }
+        if self.culling_mode == CullingMode::Gpu && self.occlusion_culling {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Hi-Z Pass"),
+                timestamp_writes: None,
+            });
+            for hiz_pyramid in self.hiz_pyramids.iter().flatten() {
+                hiz_pyramid.build(
+                    &mut compute_pass,
+                    &self.compute_pipelines[1],
+                    &self.compute_pipelines[2],
+                );
+            }
+        }
+    }
+
+    /// Creates missing [`CullTarget`]s and uploads the camera frustums for the compute pass.
+    fn prepare_gpu_culling(&mut self, aspect: f32) {
+        self.cull_targets.resize_with(self.cameras.len(), Vec::new);
+
+        for (((camera, lens_shift), hiz_pyramid), targets) in self
+            .cameras
+            .iter()
+            .zip(&self.camera_lens_shifts)
+            .zip(&self.hiz_pyramids)
+            .zip(&mut self.cull_targets)
+        {
+            let Some(camera) = camera else {
+                targets.clear();
+                continue;
+            };
+            let hiz = hiz_pyramid.as_ref().filter(|_| self.occlusion_culling);
+            let view_projection = camera.build_shifted_view_projection_matrix(aspect, *lens_shift);
+            let viewport = match &camera.viewport {
+                Some(viewport) => [viewport.x, viewport.y, viewport.w, viewport.h],
+                None => [
+                    0.0,
+                    0.0,
+                    self.surface_config.width as f32,
+                    self.surface_config.height as f32,
+                ],
+            };
+
+            targets.resize_with(self.models.len(), || None);
+            for ((model, instance_buffer), target) in self
+                .models
+                .iter()
+                .zip(&self.instance_buffers)
+                .zip(targets.iter_mut())
+            {
+                let (Some(model), Some(instance_buffer)) = (model, instance_buffer) else {
+                    *target = None;
+                    continue;
+                };
+                if model.meshes.is_empty() {
+                    continue;
+                }
+
+                target
+                    .get_or_insert_with(|| {
+                        CullTarget::new(
+                            &self.device,
+                            &self.cull_bind_group_layout,
+                            model,
+                            instance_buffer,
+                        )
+                    })
+                    .prepare(
+                        &self.queue,
+                        view_projection,
+                        viewport,
+                        hiz,
+                        model,
+                        instance_buffer,
+                    );
+            }
+        }
+    }
+
+    /// Sets where instances are culled, see [`CullingMode`].
+    pub fn set_culling_mode(&mut self, culling_mode: CullingMode) {
+        self.culling_mode = culling_mode;
+        if culling_mode == CullingMode::Cpu {
+            self.cull_targets.clear();
+        }
+    }
+
+    /// Enables occlusion culling for [`CullingMode::Gpu`], the default. After rendering a depth pyramid is built, which is used to skip instances hidden behind others in the next frame.
+    /// Every camera has its own depth pyramid, so cameras with overlapping viewports don't cull instances hidden only from the other camera.
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: bool) {
+        self.occlusion_culling = occlusion_culling;
+        for hiz_pyramid in self.hiz_pyramids.iter_mut().flatten() {
+            hiz_pyramid.built = false;
+        }
+    }
+
+    /// Adds a WGSL file for `add_material_pipeline()`, which can also be included by other files. See [`ShaderLoader`] for the preprocessor.
+    pub fn add_shader_source(&mut self, file_name: &str, source: impl Into<String>) {
+        self.shader_loader.add_source(file_name, source);
+    }
+
+    /// Adds a pipeline drawing models with a custom shader instead of the built-in one and returns it's id, see `set_model_material_pipeline()`.
+    ///
+    /// The shader needs a `vs_main` and `fs_main` entry point and is drawn with the bind groups of the built-in model pipeline,
+    /// which `#include "material.wgsl"` declares: the textures and material of the mesh in group 0, the camera in group 1,
+    /// the light in group 2 and the voxel scene in group 3. The uniform is bound at `@group(0) @binding(5)`,
+    /// it has to be laid out like the struct the shader declares for it. A shader declaring a larger struct is refused with a [`ShaderError`].
+    pub async fn add_material_pipeline<U: bytemuck::Pod>(
+        &mut self,
+        shader: ShaderKey,
+        uniform: U,
+    ) -> Result<usize, ShaderError> {
+        let Some(uniform_size) = wgpu::BufferSize::new(std::mem::size_of::<U>() as u64) else {
+            return Err(ShaderError {
+                file_name: shader.file_name,
+                line: None,
+                message: "the material uniform can't be empty".to_string(),
+            });
+        };
+        // The uniform binding has the size of the uniform, so validation refuses shaders expecting a larger one
+        let bind_group_layout =
+            self.device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        &self.texture_layout_entries[..],
+                        &[wgpu::BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(uniform_size),
+                            },
+                            count: None,
+                        }],
+                    ]
+                    .concat(),
+                    label: Some("material_bind_group_layout"),
+                });
+        let pipeline_layout = Arc::new(self.device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Material Pipeline Layout"),
+                bind_group_layouts: &[
+                    &bind_group_layout,
+                    &self.camera_bind_group_layout,
+                    &self.light_bind_group_layout,
+                    &self.voxel_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            },
+        ));
+        let mut recipe = PipelineRecipe::material(
+            self.material_pipelines.len(),
+            shader,
+            pipeline_layout,
+            self.surface_config.format,
+            Texture::DEPTH_FORMAT,
+        );
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = self
+            .shader_loader
+            .module(&self.device, &recipe.shader)
+            .map(|shader| recipe.create(&self.device, &shader));
+        let pipeline = match (pipeline, self.device.pop_error_scope().await) {
+            (Ok(Pipeline::Render(pipeline)), None) => pipeline,
+            (Err(error), _) => return Err(error),
+            (Ok(_), error) => {
+                // Don't keep the broken variant around
+                self.shader_loader.invalidate(&recipe.shader.file_name);
+                return Err(ShaderError {
+                    file_name: recipe.shader.file_name,
+                    line: None,
+                    message: error.map_or(String::new(), |error| error.to_string()),
+                });
+            }
+        };
+        recipe.shader_files = self.shader_loader.files(&recipe.shader).unwrap().to_vec();
+        self.pipeline_recipes.push(recipe);
+
+        let uniform_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Material Pipeline Uniform Buffer"),
+                contents: bytemuck::bytes_of(&uniform),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        self.material_pipelines.push(MaterialPipeline {
+            pipeline,
+            bind_group_layout,
+            uniform_buffer,
+        });
+        Ok(self.material_pipelines.len() - 1)
+    }
+
+    /// This can be used for adding custom shaders using a [`wgpu::RenderPipelineDescriptor`].
+    #[deprecated(note = "the pipeline is never drawn, use `add_material_pipeline()` instead")]
+    pub fn add_render_pipeline(&mut self, desc: &wgpu::RenderPipelineDescriptor) {
+        let render_pipeline = self.device.create_render_pipeline(desc);
+        self.render_pipelines.push(render_pipeline);
+    }
+
+    /// Replaces the uniform of a material pipeline, it has to be of the type the pipeline was added with.
+    pub fn set_material_uniform<U: bytemuck::Pod>(
+        &mut self,
+        pipeline_id: usize,
+        uniform: U,
+    ) -> anyhow::Result<()> {
+        let Some(material_pipeline) = self.material_pipelines.get(pipeline_id) else {
+            anyhow::bail!("there is no material pipeline {pipeline_id}");
+        };
+        let uniform_buffer = &material_pipeline.uniform_buffer;
+        if uniform_buffer.size() != std::mem::size_of::<U>() as wgpu::BufferAddress {
+            anyhow::bail!(
+                "the uniform has {} bytes, the pipeline was added with {}",
+                std::mem::size_of::<U>(),
+                uniform_buffer.size()
+            );
+        }
+        self.queue
+            .write_buffer(uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        Ok(())
+    }
+
+    /// Draws the meshes of a model with a material pipeline from `add_material_pipeline()`, `None` draws them with the built-in pipeline again.
+    pub fn set_model_material_pipeline(&mut self, model_id: usize, pipeline_id: Option<usize>) {
+        match pipeline_id {
+            Some(pipeline_id) => self.assign_material_pipeline(model_id, pipeline_id),
+            None => {
+                self.material_assignments.remove(&model_id);
+            }
+        }
+    }
+
+    pub fn model_material_pipeline(&self, model_id: usize) -> Option<usize> {
+        self.material_assignments
+            .get(&model_id)
+            .map(|assignment| assignment.pipeline_id)
+    }
+
+    /// Binds the materials of the model to the uniform of the pipeline.
+    fn assign_material_pipeline(&mut self, model_id: usize, pipeline_id: usize) {
+        let model = self.models[model_id].as_ref().unwrap();
+        let material_pipeline = &self.material_pipelines[pipeline_id];
+        let materials = model
+            .materials
+            .iter()
+            .map(|material| {
+                material.with_custom_uniform(
+                    &self.device,
+                    &material_pipeline.bind_group_layout,
+                    &material_pipeline.uniform_buffer,
+                )
+            })
+            .collect();
+        self.material_assignments.insert(
+            model_id,
+            MaterialAssignment {
+                pipeline_id,
+                materials,
+            },
+        );
+    }
 
     /// Adds a [`Model`] and returns it's id
     pub async fn load_model_instanced(
@@ -395,29 +1323,35 @@ impl RenderState {
         model_file: &str,
         instances: Vec<Instance>,
     ) -> usize {
+        let sampler_settings = self.sampler_settings;
+        self.load_model_with_sampler(model_file, instances, |_, _| sampler_settings)
+            .await
+    }
+
+    /// Like `load_model_instanced()`, but every texture of the model is sampled with the [`SamplerSettings`]
+    /// returned for the name of its material and its [`TextureSlot`] instead of the default ones,
+    /// e.g. to repeat a pixel art diffuse texture while keeping its normal map smooth.
+    pub async fn load_model_with_sampler(
+        &mut self,
+        model_file: &str,
+        instances: Vec<Instance>,
+        mut sampler_settings: impl FnMut(&str, TextureSlot) -> SamplerSettings,
+    ) -> usize {
+        let device = &self.device;
+        let sampler_cache = &mut self.sampler_cache;
         let model = load_model(
             model_file,
             &self.device,
             &self.queue,
             &self.texture_bind_group_layout,
             instances,
+            |material, slot| sampler_cache.get(device, &sampler_settings(material, slot)),
+            &mut self.asset_cache,
         )
         .await
         .unwrap();
 
-        let instance_data = model
-            .instances
-            .iter()
-            .map(Instance::to_raw)
-            .collect::<Vec<_>>();
-
-        let instance_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            });
+        let instance_buffer = InstanceBuffer::new(&self.device, &model.instances);
 
         if self.models.iter().any(|option| option.is_none()) {
             let index = self
@@ -440,24 +1374,319 @@ impl RenderState {
         }
     }
 
-    /// Remove a [`Model`] from the [`RenderState`]
+    /// Sets how the textures of models loaded afterwards are filtered.
+    pub fn set_texture_filtering(&mut self, filtering: TextureFiltering) {
+        self.sampler_settings.filtering = filtering;
+    }
+
+    pub fn texture_filtering(&self) -> TextureFiltering {
+        self.sampler_settings.filtering
+    }
+
+    /// Sets how the textures of models loaded with `load_model_instanced()` afterwards are sampled.
+    pub fn set_sampler_settings(&mut self, settings: SamplerSettings) {
+        self.sampler_settings = settings;
+    }
+
+    pub fn sampler_settings(&self) -> SamplerSettings {
+        self.sampler_settings
+    }
+
+    /// Remove a [`Model`] from the [`RenderState`], its textures and materials are freed unless another model uses them too.
     pub fn remove_model(&mut self, model_id: usize) {
         self.models[model_id] = None;
         self.instance_buffers[model_id] = None;
+        self.material_assignments.remove(&model_id);
+        for targets in &mut self.cull_targets {
+            if let Some(target) = targets.get_mut(model_id) {
+                *target = None;
+            }
+        }
+        self.asset_cache.prune();
+    }
+
+    /// The number of distinct textures used by the loaded models.
+    pub fn loaded_texture_count(&self) -> usize {
+        self.asset_cache.texture_count()
+    }
+
+    /// The number of distinct materials used by the loaded models.
+    pub fn loaded_material_count(&self) -> usize {
+        self.asset_cache.material_count()
+    }
+
+    /// Watches the files of the loaded models, their textures and the shaders for changes, see `reload_changed_assets()`.
+    /// Meant for development, changed files are read from the `res/` directory of the crate and the shaders from the
+    /// directory set with `set_shader_dir()`, which is the `src/` directory of the crate unless another one was set.
+    /// Both default directories are paths of the checkout the crate was built from, so apart from shaders in a directory
+    /// of your own nothing is reloaded once the binary runs somewhere else.
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        if enabled != self.file_watcher.is_some() {
+            self.file_watcher = enabled.then(FileWatcher::default);
+            let default_dir = pipeline::shader_dir();
+            if enabled && self.shader_loader.dir().is_none() {
+                self.shader_loader.set_dir(Some(default_dir));
+            } else if !enabled && self.shader_loader.dir() == Some(&default_dir) {
+                self.shader_loader.set_dir(None);
+            }
+        }
+    }
+
+    /// Reads WGSL files from the directory before falling back to the shaders built into the crate,
+    /// `None` only uses the built-in ones. Pipelines that already exist keep their shaders until they are reloaded.
+    pub fn set_shader_dir(&mut self, dir: Option<PathBuf>) {
+        self.shader_loader.set_dir(dir);
+    }
+
+    pub fn shader_dir(&self) -> Option<&PathBuf> {
+        self.shader_loader.dir()
+    }
+
+    pub fn hot_reload(&self) -> bool {
+        self.file_watcher.is_some()
     }
 
+    /// Reloads every watched file that changed since the last call and rebuilds the materials and pipelines using it in place.
+    /// Files that fail to load or compile are reported and their previous version stays in use.
+    /// Returns nothing unless hot reloading is enabled with `set_hot_reload()`. The files are only checked every 250 milliseconds,
+    /// so calling this every frame doesn't query the file system every frame.
+    pub async fn reload_changed_assets(&mut self) -> Vec<Result<ReloadedAsset, ReloadError>> {
+        let Some(mut file_watcher) = self.file_watcher.take() else {
+            return vec![];
+        };
+
+        let mut textures = HashMap::new();
+        for file_name in self.asset_cache.texture_files() {
+            textures.insert(resources::source_path(file_name), file_name.to_string());
+        }
+        let mut model_files: HashMap<PathBuf, (String, Vec<usize>)> = HashMap::new();
+        for (model_id, model) in self.models.iter().enumerate() {
+            let Some(model) = model else {
+                continue;
+            };
+            for file_name in model.material_libraries.iter().chain([&model.file_name]) {
+                model_files
+                    .entry(resources::source_path(file_name))
+                    .or_insert_with(|| (file_name.clone(), vec![]))
+                    .1
+                    .push(model_id);
+            }
+        }
+        let mut shaders = HashMap::new();
+        if let Some(shader_dir) = self.shader_loader.dir() {
+            for file_name in self
+                .pipeline_recipes
+                .iter()
+                .flat_map(|recipe| recipe.shader_files.iter())
+            {
+                shaders.insert(shader_dir.join(file_name), file_name.to_string());
+            }
+        }
+
+        file_watcher.watch(
+            textures
+                .keys()
+                .chain(model_files.keys())
+                .chain(shaders.keys())
+                .cloned(),
+        );
+        let changed = file_watcher.changed();
+        self.file_watcher = Some(file_watcher);
+
+        let mut results = vec![];
+        for path in changed {
+            if let Some(file_name) = shaders.get(&path) {
+                results.push(
+                    self.reload_shader(file_name)
+                        .await
+                        .map(|_| ReloadedAsset::Shader(file_name.clone())),
+                );
+                continue;
+            }
+
+            let file_name = textures
+                .get(&path)
+                .or_else(|| model_files.get(&path).map(|(file_name, _)| file_name))
+                .unwrap();
+            if let Err(error) = resources::refresh_resource(file_name) {
+                results.push(Err(ReloadError {
+                    file_name: file_name.clone(),
+                    message: error.to_string(),
+                }));
+                continue;
+            }
+            if textures.contains_key(&path) {
+                results.push(
+                    self.reload_texture(file_name)
+                        .await
+                        .map(|_| ReloadedAsset::Texture(file_name.clone())),
+                );
+            }
+            for &model_id in model_files.get(&path).map_or(&[][..], |(_, ids)| ids) {
+                results.push(
+                    self.reload_model(model_id)
+                        .await
+                        .map(|_| ReloadedAsset::Model(model_id)),
+                );
+            }
+        }
+
+        results
+    }
+
+    async fn reload_texture(&mut self, file_name: &str) -> Result<(), ReloadError> {
+        let replaced = self
+            .asset_cache
+            .reload_texture(
+                file_name,
+                &self.device,
+                &self.queue,
+                &self.texture_bind_group_layout,
+            )
+            .await
+            .map_err(|error| ReloadError {
+                file_name: file_name.to_string(),
+                message: error.to_string(),
+            })?;
+
+        let mut changed_models = vec![];
+        for (model_id, model) in self.models.iter_mut().enumerate() {
+            let Some(model) = model else {
+                continue;
+            };
+            for material in &mut model.materials {
+                if let Some((_, new)) = replaced
+                    .iter()
+                    .find(|(previous, _)| Arc::ptr_eq(previous, material))
+                {
+                    *material = new.clone();
+                    if changed_models.last() != Some(&model_id) {
+                        changed_models.push(model_id);
+                    }
+                }
+            }
+        }
+        // Material pipelines bind copies of the materials
+        for model_id in changed_models {
+            if let Some(assignment) = self.material_assignments.get(&model_id) {
+                self.assign_material_pipeline(model_id, assignment.pipeline_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the model from its files again, keeping its id, instances and samplers.
+    async fn reload_model(&mut self, model_id: usize) -> Result<(), ReloadError> {
+        let previous = self.models[model_id].as_ref().unwrap();
+        let file_name = previous.file_name.clone();
+        let device = &self.device;
+        let sampler_cache = &mut self.sampler_cache;
+        let sampler_settings = self.sampler_settings;
+        let sampler = |name: &str, slot| {
+            match previous
+                .materials
+                .iter()
+                .find(|material| material.name == name)
+            {
+                Some(material) => match slot {
+                    TextureSlot::Diffuse => material.diffuse_texture.sampler.clone(),
+                    TextureSlot::Normal => material.normal_texture.sampler.clone(),
+                },
+                // Materials added to the file get the default sampler
+                None => sampler_cache.get(device, &sampler_settings),
+            }
+        };
+        let model = load_model(
+            &file_name,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            previous.instances.clone(),
+            sampler,
+            &mut self.asset_cache,
+        )
+        .await
+        .map_err(|error| ReloadError {
+            file_name,
+            message: error.to_string(),
+        })?;
+
+        self.models[model_id] = Some(model);
+        if let Some(assignment) = self.material_assignments.get(&model_id) {
+            self.assign_material_pipeline(model_id, assignment.pipeline_id);
+        }
+        // The meshes might have changed
+        for targets in &mut self.cull_targets {
+            if let Some(target) = targets.get_mut(model_id) {
+                *target = None;
+            }
+        }
+        self.asset_cache.prune();
+        Ok(())
+    }
+
+    /// Creates every built-in pipeline using the shader file again, only replacing them if all of them compile.
+    async fn reload_shader(&mut self, file_name: &str) -> Result<(), ReloadError> {
+        self.shader_loader.invalidate(file_name);
+
+        let mut pipelines = vec![];
+        for (index, recipe) in self.pipeline_recipes.iter().enumerate() {
+            if !recipe.shader_files.iter().any(|file| file == file_name) {
+                continue;
+            }
+
+            self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let pipeline = self
+                .shader_loader
+                .module(&self.device, &recipe.shader)
+                .map(|shader| recipe.create(&self.device, &shader));
+            let error = match (pipeline, self.device.pop_error_scope().await) {
+                (Ok(pipeline), None) => {
+                    let files = self.shader_loader.files(&recipe.shader).unwrap().to_vec();
+                    pipelines.push((index, pipeline, files));
+                    continue;
+                }
+                (Err(error), _) => error.to_string(),
+                (Ok(_), Some(error)) => error.to_string(),
+            };
+            // Don't keep the broken variants around
+            self.shader_loader.invalidate(file_name);
+            return Err(ReloadError {
+                file_name: file_name.to_string(),
+                message: error,
+            });
+        }
+
+        for (index, pipeline, files) in pipelines {
+            let recipe = &mut self.pipeline_recipes[index];
+            recipe.shader_files = files;
+            match (recipe.slot, pipeline) {
+                (PipelineSlot::Render(index), Pipeline::Render(pipeline)) => {
+                    self.render_pipelines[index] = pipeline
+                }
+                (PipelineSlot::Compute(index), Pipeline::Compute(pipeline)) => {
+                    self.compute_pipelines[index] = pipeline
+                }
+                (PipelineSlot::Material(index), Pipeline::Render(pipeline)) => {
+                    self.material_pipelines[index].pipeline = pipeline
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an [`Instance`] to a [`Model`] and returns it's id.
+    /// The instance buffer only gets recreated when it runs out of capacity, in which case the capacity is doubled.
     pub fn push_instance(&mut self, model_id: usize, instance: Instance) -> usize {
         let model = self.models[model_id].as_mut().unwrap();
         let index = model.instances.len();
         model.instances.push(instance);
 
-        if !self
-            .recreate_instance_buffers
-            .iter()
-            .any(|id| id == &model_id)
-        {
-            self.recreate_instance_buffers.push(model_id);
-        }
+        self.instance_buffers[model_id]
+            .as_mut()
+            .unwrap()
+            .push(&instance);
 
         index
     }
@@ -472,42 +1701,189 @@ impl RenderState {
             .instances
             .remove(instance_id);
 
-        if !self
-            .recreate_instance_buffers
-            .iter()
-            .any(|id| id == &model_id)
-        {
-            self.recreate_instance_buffers.push(model_id);
-        }
+        self.instance_buffers[model_id]
+            .as_mut()
+            .unwrap()
+            .remove(instance_id);
     }
 
-    /// Override the specified instance.
+    /// Override the specified instance. Only the changed instances are uploaded on the next `render()`.
     pub fn override_instance(
         &mut self,
         model_id: usize,
         instance_id: usize,
         instance_override: Instance,
     ) {
-        self.queue.write_buffer(
-            self.instance_buffers[model_id].as_ref().unwrap(),
-            instance_id as u64 * 100,
-            bytemuck::cast_slice(&[instance_override.to_raw()]),
-        );
+        self.instance_buffers[model_id]
+            .as_mut()
+            .unwrap()
+            .set(instance_id, &instance_override);
         self.models[model_id].as_mut().unwrap().instances[instance_id] = instance_override;
     }
 
+    /// Replaces all [`Instance`]s of a [`Model`] at once. This is a lot cheaper than many `push_instance()` or `override_instance()` calls for bulk updates.
+    /// The instance ids afterwards correspond to the indices in `instances` and all of them are visible.
+    pub fn set_instances(&mut self, model_id: usize, instances: &[Instance]) {
+        self.instance_buffers[model_id]
+            .as_mut()
+            .unwrap()
+            .replace(instances);
+        self.models[model_id].as_mut().unwrap().instances = instances.to_vec();
+    }
+
+    /// Shows or hides an [`Instance`] without changing any instance ids. Hidden instances are not drawn, but can still be modified using `override_instance()`.
+    pub fn set_instance_visible(&mut self, model_id: usize, instance_id: usize, visible: bool) {
+        let instance_buffer = self.instance_buffers[model_id].as_mut().unwrap();
+        if visible {
+            let instance = &self.models[model_id].as_ref().unwrap().instances[instance_id];
+            instance_buffer.show(instance_id, instance);
+        } else {
+            instance_buffer.hide(instance_id);
+        }
+    }
+
+    pub fn is_instance_visible(&self, model_id: usize, instance_id: usize) -> bool {
+        self.instance_buffers[model_id]
+            .as_ref()
+            .unwrap()
+            .is_visible(instance_id)
+    }
+
+    /// The number of instances of the [`Model`] that aren't hidden with `set_instance_visible()`.
+    pub fn visible_instance_count(&self, model_id: usize) -> u32 {
+        self.instance_buffers[model_id]
+            .as_ref()
+            .unwrap()
+            .visible_count()
+    }
+
     /// Returns a reference to the requested [`Instance`]. To modify an [`Instance`] use `override_instance()`.
     pub fn get_instance(&self, model_id: usize, instance_id: usize) -> &Instance {
         &self.models[model_id].as_ref().unwrap().instances[instance_id]
     }
 
+    /// Sets the smooth minimum function used for blending neighbouring voxels together.
+    /// `blend_radius` is the distance over which the surfaces of two voxels are blended.
+    pub fn set_smooth_min(&mut self, smooth_min: SmoothMin, blend_radius: f32) {
+        self.voxel_storage.smooth_min = smooth_min;
+        self.voxel_storage.blend_radius = blend_radius;
+    }
+
+    /// Sets the quality and budget of the per-voxel global illumination, see [`GiSettings`].
+    pub fn set_gi_settings(&mut self, settings: GiSettings) {
+        self.voxel_gi.settings = settings;
+    }
+
+    pub fn gi_settings(&self) -> GiSettings {
+        self.voxel_gi.settings
+    }
+
+    /// Enables reprojection and sets the rate the scene is rendered at, see [`ReprojectionSettings`].
+    pub fn set_reprojection_settings(&mut self, settings: ReprojectionSettings) {
+        self.reprojection.settings = settings;
+    }
+
+    pub fn reprojection_settings(&self) -> ReprojectionSettings {
+        self.reprojection.settings
+    }
+
+    /// Sets how reflections on glossy voxels and meshes are rendered, see [`ReflectionSettings`].
+    pub fn set_reflection_settings(&mut self, settings: ReflectionSettings) {
+        self.voxel_storage.reflections = settings;
+    }
+
+    pub fn reflection_settings(&self) -> ReflectionSettings {
+        self.voxel_storage.reflections
+    }
+
+    /// Adds a [`VoxelVolume`] and returns it's id
+    pub fn add_voxel_volume(&mut self, volume: VoxelVolume) -> usize {
+        self.voxel_storage.add(volume)
+    }
+
+    /// Loads a MagicaVoxel `.vox` file as a [`VoxelVolume`], see [`RenderState::get_voxel_volume`] to access its voxels.
+    /// `voxel_size` is the edge length of a voxel in world units. Fails if the file is missing or malformed or the size isn't positive.
+    ///
+    /// With `hollow` the voxels hidden behind all six neighbours are skipped, which saves a lot of points for large solid models.
+    /// The volume is only a shell then, so removing voxels from it, for example with a [`BrushOperation::Subtract`](edit::BrushOperation::Subtract) brush, exposes empty space.
+    pub async fn load_voxel_volume(
+        &mut self,
+        file_name: &str,
+        voxel_size: f32,
+        hollow: bool,
+    ) -> anyhow::Result<usize> {
+        let volume = load_vox(file_name, voxel_size, hollow).await?;
+        Ok(self.add_voxel_volume(volume))
+    }
+
+    /// Converts the meshes of a loaded [`Model`] into a [`VoxelVolume`] in model space, which can be added with [`RenderState::add_voxel_volume`].
+    /// `voxel_size` is the edge length of a voxel in world units, the voxel colors are sampled from the diffuse textures.
+    /// The meshes and textures are read back from the GPU, so this waits until all submitted work is done.
+    pub fn voxelize_model(&self, model_id: usize, voxel_size: f32) -> anyhow::Result<VoxelVolume> {
+        let Some(model) = self.models.get(model_id).and_then(Option::as_ref) else {
+            anyhow::bail!("there is no model with the id {model_id}");
+        };
+        voxelize_model(model, &self.device, &self.queue, voxel_size)
+    }
+
+    /// Replaces a [`VoxelVolume`], which drops the edit history of the volume.
+    /// As long as it doesn't grow beyond the space reserved for it on the GPU, only this volume is uploaded again.
+    pub fn update_voxel_volume(&mut self, volume_id: usize, volume: VoxelVolume) {
+        self.voxel_storage.update(volume_id, volume);
+        self.voxel_edits.forget(volume_id);
+    }
+
+    /// Remove a [`VoxelVolume`] from the [`RenderState`]
+    pub fn remove_voxel_volume(&mut self, volume_id: usize) {
+        self.voxel_storage.remove(volume_id);
+        self.voxel_edits.forget(volume_id);
+    }
+
+    /// Adds, subtracts or paints the voxels of a [`VoxelVolume`] with a [`Brush`]. Only the changed voxels are uploaded.
+    /// Returns `false` if the brush didn't change anything, otherwise the edit can be reverted with `undo_voxel_edit()`.
+    pub fn apply_brush(&mut self, volume_id: usize, brush: &Brush) -> bool {
+        self.voxel_edits
+            .apply(&mut self.voxel_storage, volume_id, brush)
+    }
+
+    /// Reverts the last brush edit. Returns `false` if there is nothing to undo.
+    pub fn undo_voxel_edit(&mut self) -> bool {
+        self.voxel_edits.undo(&mut self.voxel_storage)
+    }
+
+    /// Applies the last undone brush edit again. Returns `false` if there is nothing to redo.
+    pub fn redo_voxel_edit(&mut self) -> bool {
+        self.voxel_edits.redo(&mut self.voxel_storage)
+    }
+
+    /// Finds the closest voxel along a ray, e.g. to find the voxel under the cursor.
+    pub fn raycast_voxels(
+        &self,
+        origin: glam::Vec3,
+        direction: glam::Vec3,
+        max_distance: f32,
+    ) -> Option<VoxelHit> {
+        edit::raycast(
+            self.voxel_storage.volumes(),
+            origin,
+            direction,
+            max_distance,
+        )
+    }
+
+    /// Returns a reference to the requested [`VoxelVolume`]. To modify a [`VoxelVolume`] use `update_voxel_volume()`.
+    pub fn get_voxel_volume(&self, volume_id: usize) -> &VoxelVolume {
+        self.voxel_storage.get(volume_id)
+    }
+
     // TODO: Removing and modifying cameras
     pub fn add_camera(&mut self, camera: Camera) -> usize {
+        self.insert_camera(camera, Vec2::ZERO)
+    }
+
+    fn insert_camera(&mut self, camera: Camera, lens_shift: Vec2) -> usize {
         let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_projection(
-            &camera,
-            self.surface_config.width as f32 / self.surface_config.height as f32,
-        );
+        camera_uniform.update_shifted_view_projection(&camera, self.aspect(), lens_shift);
 
         let camera_buffer = self
             .device
@@ -517,24 +1893,8 @@ impl RenderState {
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
-        let camera_bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                    label: Some("camera_bind_group_layout"),
-                });
-
         let camera_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
+            layout: &self.camera_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: camera_buffer.as_entire_binding(),
@@ -543,37 +1903,208 @@ impl RenderState {
         });
 
         let index = self.cameras.len();
+        let (depth_texture, hiz_pyramid) = self.create_camera_depth();
+
         self.cameras.push(Some(camera));
+        self.camera_lens_shifts.push(lens_shift);
+        self.depth_textures.push(Some(depth_texture));
+        self.hiz_pyramids.push(Some(hiz_pyramid));
         self.camera_uniforms.push(Some(camera_uniform));
         self.camera_buffers.push(Some(camera_buffer));
         self.camera_bind_groups.push(Some(camera_bind_group));
+        self.culling_stats.push(None);
 
         index
     }
 
+    /// Creates the depth texture of a camera and the depth pyramid built from it for occlusion culling.
+    fn create_camera_depth(&self) -> (Texture, HiZPyramid) {
+        let depth_texture =
+            Texture::create_depth_texture(&self.device, &self.surface_config, "depth_texture");
+        let hiz_pyramid = HiZPyramid::new(
+            &self.device,
+            &self.hiz_layouts,
+            &depth_texture,
+            &self.surface_config,
+        );
+        (depth_texture, hiz_pyramid)
+    }
+
     /// This currently only sets the corresponding element in the cameras [`Vec`] to [`None`].
     /// When adding new [`Camera`]s the [`None`] values are not reused to keep the rendering order of the cameras. *This is subject to change!*
     pub fn remove_camera(&mut self, camera_id: usize) {
         self.cameras[camera_id] = None;
+        self.camera_lens_shifts[camera_id] = Vec2::ZERO;
+        self.depth_textures[camera_id] = None;
+        self.hiz_pyramids[camera_id] = None;
         self.camera_uniforms[camera_id] = None;
         self.camera_bind_groups[camera_id] = None;
         self.camera_buffers[camera_id] = None;
+        self.culling_stats[camera_id] = None;
+    }
+
+    /// Returns how many instances the [`Camera`] culled during the last `render()`, or [`None`] if it wasn't rendered yet or the default [`CullingMode::Gpu`] is used, which culls on the GPU.
+    pub fn culling_stats(&self, camera_id: usize) -> Option<CullingStats> {
+        self.culling_stats[camera_id]
     }
 
     pub fn override_camera(&mut self, camera_id: usize, camera_override: Camera) {
-        self.cameras[camera_id] = Some(camera_override);
+        self.write_camera(camera_id, camera_override, Vec2::ZERO);
+    }
+
+    fn write_camera(&mut self, camera_id: usize, camera: Camera, lens_shift: Vec2) {
+        let aspect = self.aspect();
+        self.cameras[camera_id] = Some(camera);
+        self.camera_lens_shifts[camera_id] = lens_shift;
         self.camera_uniforms[camera_id]
+            .as_mut()
             .unwrap()
-            .update_view_projection(
-                &camera_override,
-                self.surface_config.width as f32 / self.surface_config.height as f32,
-            );
+            .update_shifted_view_projection(&camera, aspect, lens_shift);
         self.queue.write_buffer(
             self.camera_buffers[camera_id].as_ref().unwrap(),
             0,
             bytemuck::cast_slice(&[self.camera_uniforms[camera_id].unwrap()]),
         );
     }
+
+    /// Adds the cameras of both eyes, which render side by side into the frame. Returns the ids of the left and right eye.
+    /// Only one [`StereoCamera`] is rendered at a time, adding another one removes the eyes of the previous one.
+    /// Fails if the convergence distance isn't positive.
+    pub fn add_stereo_camera(
+        &mut self,
+        camera: StereoCamera,
+        layout: StereoLayout,
+    ) -> anyhow::Result<[usize; 2]> {
+        camera.validate()?;
+        self.remove_stereo_camera();
+
+        let [(left, left_shift), (right, right_shift)] = camera.eyes(
+            self.surface_config.width as f32,
+            self.surface_config.height as f32,
+        );
+        let eyes = [
+            self.insert_camera(left, left_shift),
+            self.insert_camera(right, right_shift),
+        ];
+        self.stereo = Some(Stereo::new(
+            &self.device,
+            camera,
+            eyes,
+            layout,
+            &self.surface_config,
+        ));
+
+        Ok(eyes)
+    }
+
+    /// Fails if the convergence distance isn't positive, does nothing if there is no [`StereoCamera`].
+    pub fn override_stereo_camera(&mut self, camera_override: StereoCamera) -> anyhow::Result<()> {
+        camera_override.validate()?;
+        if let Some(stereo) = &mut self.stereo {
+            stereo.camera = camera_override;
+            self.update_stereo_eyes();
+        }
+        Ok(())
+    }
+
+    fn update_stereo_eyes(&mut self) {
+        let Some(stereo) = &self.stereo else {
+            return;
+        };
+        let eyes = stereo.eyes;
+        let cameras = stereo.eye_cameras(&self.surface_config);
+        for (eye, (camera, lens_shift)) in eyes.into_iter().zip(cameras) {
+            self.write_camera(eye, camera, lens_shift);
+        }
+    }
+
+    /// Removes the cameras of the [`StereoCamera`].
+    pub fn remove_stereo_camera(&mut self) {
+        if let Some(stereo) = self.stereo.take() {
+            for eye in stereo.eyes {
+                self.remove_camera(eye);
+            }
+        }
+    }
+
+    /// The two layer array texture holding the eyes of the last frame, left eye in layer 0.
+    /// Returns [`None`] unless the [`StereoCamera`] uses [`StereoLayout::LayeredArray`].
+    pub fn stereo_texture(&self) -> Option<&wgpu::Texture> {
+        self.stereo.as_ref().and_then(Stereo::array_texture)
+    }
+
+    /// Reads the images of both eyes of the last frame, waiting for the GPU to finish.
+    /// With [`StereoLayout::LayeredArray`] they are read from the layers of `stereo_texture()`, otherwise from the halves of the frame.
+    /// Fails if the [`RenderState`] isn't headless or no [`StereoCamera`] was added.
+    pub fn read_eye_images(&self) -> anyhow::Result<[image::RgbaImage; 2]> {
+        let Some(stereo) = &self.stereo else {
+            anyhow::bail!("no stereo camera was added");
+        };
+        let Some(frame) = &self.headless_texture else {
+            anyhow::bail!("only headless render states can read the eye images back");
+        };
+
+        stereo.read_eye_images(&self.device, &self.queue, frame)
+    }
+}
+
+fn create_instance() -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::VULKAN,
+        ..Default::default()
+    })
+}
+
+async fn request_device(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+) -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptionsBase {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface,
+            force_fallback_adapter: false,
+        })
+        .await
+        .ok_or_else(|| anyhow::anyhow!("no adapter supporting Vulkan was found"))?;
+
+    // Compressed textures the adapter can't sample are decompressed when they are loaded
+    let features = adapter.features()
+        & (wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                features,
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        )
+        .await?;
+
+    Ok((adapter, device, queue))
+}
+
+fn create_headless_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("headless_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage,
+        view_formats: &[],
+    })
 }
 
 fn create_render_pipeline(
@@ -582,20 +2113,18 @@ fn create_render_pipeline(
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
+    shader: &wgpu::ShaderModule,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
         layout: Some(layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             buffers: vertex_layouts,
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
@@ -0,0 +1,8 @@
+// Matches `LightUniform`
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+// The light surfaces receive without facing the light, we don't need (or want) much of it
+const AMBIENT_STRENGTH: f32 = 0.1;
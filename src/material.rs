@@ -0,0 +1,17 @@
+use crate::model::Material;
+
+/// A pipeline drawing models with a custom shader, see `RenderState::add_material_pipeline()`.
+pub(crate) struct MaterialPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+    /// The material bind group layout with the size of the uniform
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    /// Bound at `@group(0) @binding(5)` next to the material of every mesh
+    pub uniform_buffer: wgpu::Buffer,
+}
+
+/// The material pipeline a model is drawn with.
+pub(crate) struct MaterialAssignment {
+    pub pipeline_id: usize,
+    /// The materials of the model with bind groups that include the uniform of the pipeline
+    pub materials: Vec<Material>,
+}
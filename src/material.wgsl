@@ -0,0 +1,60 @@
+// The bindings and vertex inputs of the model pipelines, shared by the built-in shader and custom material shaders
+// Custom material shaders can bind a uniform of their own at @group(0) @binding(5)
+
+#include "voxel_types.wgsl"
+#include "camera_uniform.wgsl"
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+#include "light_uniform.wgsl"
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+@group(3) @binding(0)
+var<uniform> voxels: Voxels;
+@group(3) @binding(1)
+var<storage, read> voxel_points: array<VoxelPoint>;
+@group(3) @binding(2)
+var<storage, read> voxel_materials: array<VoxelMaterial>;
+@group(3) @binding(3)
+var<storage, read> cell_offsets: array<u32>;
+@group(3) @binding(4)
+var<storage, read> cell_ends: array<u32>;
+@group(3) @binding(5)
+var<storage, read> cell_indices: array<u32>;
+@group(3) @binding(6)
+var<storage, read> voxel_irradiance: array<VoxelIrradiance>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+}
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+#ifdef NORMAL_MAP
+@group(0)@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+#endif
+
+struct Material {
+    roughness: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
@@ -1,47 +1,134 @@
-use std::ops::Range;
+use std::{ops::Range, sync::Arc};
 
-use crate::{instance::Instance, texture};
+use anyhow::bail;
+use wgpu::util::DeviceExt;
+
+use crate::{culling::Aabb, instance::Instance, texture};
 
 pub struct Model {
+    /// The file the model was loaded from
+    pub file_name: String,
+    /// The `.mtl` files the materials were loaded from
+    pub material_libraries: Vec<String>,
     pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    /// Shared with other models using the same material
+    pub materials: Vec<Arc<Material>>,
     pub instances: Vec<Instance>,
+    /// Bounds of all meshes in model space
+    pub bounds: Aabb,
 }
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    pub diffuse_texture: Arc<texture::Texture>,
+    pub normal_texture: Arc<texture::Texture>,
+    /// Between 0 (mirror) and 1 (diffuse), used for the reflections
+    pub roughness: f32,
     pub bind_group: wgpu::BindGroup,
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    roughness: f32,
+    _padding: [f32; 3],
+}
+
 impl Material {
+    /// The textures are sampled with filtering samplers, so formats that can't be filtered like
+    /// [`wgpu::TextureFormat::Rgba32Float`] are refused.
     pub fn new(
         device: &wgpu::Device,
         name: &str,
-        diffuse_texture: texture::Texture,
-        normal_texture: texture::Texture,
+        diffuse_texture: Arc<texture::Texture>,
+        normal_texture: Arc<texture::Texture>,
+        roughness: f32,
         layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        for texture in [&diffuse_texture, &normal_texture] {
+            let format = texture.texture.format();
+            if format.sample_type(None) != Some(wgpu::TextureSampleType::Float { filterable: true })
+            {
+                bail!("material {name} can't use a texture of the unfilterable format {format:?}");
+            }
+        }
+
+        Ok(Self::create(
+            device,
+            name,
+            diffuse_texture,
+            normal_texture,
+            roughness,
+            layout,
+            None,
+        ))
+    }
+
+    /// A copy of the material for the bind group layout of a material pipeline, which binds the uniform of the pipeline at binding 5.
+    pub fn with_custom_uniform(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        custom_uniform: &wgpu::Buffer,
     ) -> Self {
+        Self::create(
+            device,
+            &self.name,
+            self.diffuse_texture.clone(),
+            self.normal_texture.clone(),
+            self.roughness,
+            layout,
+            Some(custom_uniform),
+        )
+    }
+
+    fn create(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: Arc<texture::Texture>,
+        normal_texture: Arc<texture::Texture>,
+        roughness: f32,
+        layout: &wgpu::BindGroupLayout,
+        custom_uniform: Option<&wgpu::Buffer>,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[MaterialUniform {
+                roughness,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ];
+        if let Some(custom_uniform) = custom_uniform {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 5,
+                resource: custom_uniform.as_entire_binding(),
+            });
+        }
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
+            entries: &entries,
             label: Some(name),
         });
 
@@ -49,6 +136,7 @@ impl Material {
             name: String::from(name),
             diffuse_texture,
             normal_texture,
+            roughness,
             bind_group,
         }
     }
@@ -60,6 +148,8 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Bounds of the vertices in model space
+    pub bounds: Aabb,
 }
 
 pub trait Vertex {
@@ -129,6 +219,15 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 
     fn draw_model(
         &mut self,
@@ -175,6 +274,23 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
     fn draw_model(
         &mut self,
         model: &'b Model,
@@ -0,0 +1,141 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    instance::InstanceRaw,
+    model::{ModelVertex, Vertex},
+    shader::ShaderKey,
+};
+
+/// The directory the WGSL files are read from when they are reloaded at runtime, unless `RenderState::set_shader_dir()` set another one.
+/// Like the resources this is the checkout the crate was built from.
+pub(crate) fn shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src")
+}
+
+/// Which pipeline of the [`crate::RenderState`] a [`PipelineRecipe`] creates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PipelineSlot {
+    Render(usize),
+    Compute(usize),
+    /// A custom material pipeline, see `RenderState::add_material_pipeline()`
+    Material(usize),
+}
+
+pub(crate) enum RecipeKind {
+    Render {
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+    },
+    Compute {
+        entry_point: &'static str,
+    },
+}
+
+/// How a built-in pipeline is created, kept so it can be created again once its shader changed.
+pub(crate) struct PipelineRecipe {
+    pub label: &'static str,
+    pub slot: PipelineSlot,
+    pub shader: ShaderKey,
+    /// The files the shader was composed from the last time the pipeline was created.
+    pub shader_files: Vec<String>,
+    pub layout: Arc<wgpu::PipelineLayout>,
+    pub kind: RecipeKind,
+}
+
+pub(crate) enum Pipeline {
+    Render(wgpu::RenderPipeline),
+    Compute(wgpu::ComputePipeline),
+}
+
+impl PipelineRecipe {
+    pub fn render(
+        label: &'static str,
+        slot: usize,
+        shader: ShaderKey,
+        layout: impl Into<Arc<wgpu::PipelineLayout>>,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+    ) -> Self {
+        Self {
+            label,
+            slot: PipelineSlot::Render(slot),
+            shader,
+            shader_files: vec![],
+            layout: layout.into(),
+            kind: RecipeKind::Render {
+                color_format,
+                depth_format,
+                vertex_layouts,
+            },
+        }
+    }
+
+    /// A custom material pipeline drawing models like the built-in model pipeline.
+    pub fn material(
+        slot: usize,
+        shader: ShaderKey,
+        layout: Arc<wgpu::PipelineLayout>,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            slot: PipelineSlot::Material(slot),
+            ..Self::render(
+                "Material Pipeline",
+                slot,
+                shader,
+                layout,
+                color_format,
+                Some(depth_format),
+                vec![ModelVertex::desc(), InstanceRaw::desc()],
+            )
+        }
+    }
+
+    pub fn compute(
+        label: &'static str,
+        slot: usize,
+        shader: ShaderKey,
+        layout: impl Into<Arc<wgpu::PipelineLayout>>,
+        entry_point: &'static str,
+    ) -> Self {
+        Self {
+            label,
+            slot: PipelineSlot::Compute(slot),
+            shader,
+            shader_files: vec![],
+            layout: layout.into(),
+            kind: RecipeKind::Compute { entry_point },
+        }
+    }
+
+    pub fn create(&self, device: &wgpu::Device, shader: &wgpu::ShaderModule) -> Pipeline {
+        match &self.kind {
+            RecipeKind::Render {
+                color_format,
+                depth_format,
+                vertex_layouts,
+            } => Pipeline::Render(crate::create_render_pipeline(
+                device,
+                &self.layout,
+                *color_format,
+                *depth_format,
+                vertex_layouts,
+                shader,
+            )),
+            RecipeKind::Compute { entry_point } => Pipeline::Compute(
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(self.label),
+                    layout: Some(&self.layout),
+                    module: shader,
+                    entry_point,
+                }),
+            ),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+#include "voxel.wgsl"
+#include "reflection.wgsl"
+
+// Vertex shader
+
+#include "camera_uniform.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+#include "light_uniform.wgsl"
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+@group(2) @binding(0)
+var<uniform> voxels: Voxels;
+@group(2) @binding(1)
+var<storage, read> voxel_points: array<VoxelPoint>;
+@group(2) @binding(2)
+var<storage, read> voxel_materials: array<VoxelMaterial>;
+@group(2) @binding(3)
+var<storage, read> cell_offsets: array<u32>;
+@group(2) @binding(4)
+var<storage, read> cell_ends: array<u32>;
+@group(2) @binding(5)
+var<storage, read> cell_indices: array<u32>;
+@group(2) @binding(6)
+var<storage, read> voxel_irradiance: array<VoxelIrradiance>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the whole viewport
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// Fragment shader
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let world = camera.inv_view_proj * vec4<f32>(ndc, 1.0);
+    return world.xyz / world.w;
+}
+
+fn shade(surface: VoxelSurface, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let indirect_color = voxel_gi(position).indirect * voxels.gi_strength;
+    let ambient_color = light.color * AMBIENT_STRENGTH + indirect_color;
+
+    let light_dir = normalize(light.position - position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0) * (1.0 - surface.roughness);
+    let specular_color = specular_strength * light.color;
+
+    return (ambient_color + diffuse_color + specular_color + surface.emission) * surface.color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let near = unproject(vec3<f32>(in.ndc, 0.0));
+    let far = unproject(vec3<f32>(in.ndc, 1.0));
+    let direction = normalize(far - near);
+
+    let t = voxel_march(near, direction, distance(near, far));
+    if t < 0.0 {
+        discard;
+    }
+
+    let position = near + direction * t;
+    let surface = voxel_surface(position);
+    let normal = voxel_normal(position);
+
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+
+    var out: FragmentOutput;
+    let color = shade(surface, position, normal, -direction);
+    out.color = vec4<f32>(apply_reflection(color, position, normal, -direction, surface.roughness), 1.0);
+    out.depth = clip.z / clip.w;
+    return out;
+}
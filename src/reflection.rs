@@ -0,0 +1,21 @@
+/// Settings for reflections on glossy voxel and mesh surfaces.
+/// Reflected rays are raymarched through the voxels, rays that miss (or surfaces rougher than `max_roughness`) reflect the sky.
+/// They are disabled by default, the roughness of meshes comes from the `Ns` of their materials, which scenes made without reflections in mind don't set with care.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReflectionSettings {
+    pub enabled: bool,
+    /// Surfaces with a higher roughness only reflect the sky instead of marching a ray.
+    pub max_roughness: f32,
+    /// How far reflected rays are marched.
+    pub max_distance: f32,
+}
+
+impl Default for ReflectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_roughness: 0.6,
+            max_distance: 50.0,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// Reflections through the voxel scene
+// The shader using these has to declare `light` and the bindings of voxel.wgsl
+
+#include "voxel.wgsl"
+
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    let t = clamp(direction.y * 0.5 + 0.5, 0.0, 1.0);
+    return mix(vec3<f32>(0.1), vec3<f32>(0.35, 0.45, 0.6), t) * light.color;
+}
+
+// The color of a voxel surface hit by a reflected ray, specular light and shadows are skipped
+fn reflected_voxel_color(position: vec3<f32>) -> vec3<f32> {
+    let surface = voxel_surface(position);
+    let normal = voxel_normal(position);
+
+    let ambient_color = light.color * AMBIENT_STRENGTH + voxel_gi(position).indirect * voxels.gi_strength;
+    let diffuse_color = light.color * max(dot(normal, normalize(light.position - position)), 0.0);
+
+    return (ambient_color + diffuse_color + surface.emission) * surface.color;
+}
+
+// Blends the reflection into the color of a surface, view_dir points from the surface to the camera
+fn apply_reflection(color: vec3<f32>, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, roughness: f32) -> vec3<f32> {
+    if voxels.reflection_max_roughness < 0.0 {
+        return color;
+    }
+    let gloss = 1.0 - clamp(roughness, 0.0, 1.0);
+    // Schlick's approximation, glossier surfaces reflect more when viewed head-on
+    let f0 = mix(0.04, 1.0, gloss * gloss * gloss * gloss);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - max(dot(normal, view_dir), 0.0), 5.0);
+    let weight = gloss * gloss * fresnel;
+    if weight <= 0.0 {
+        return color;
+    }
+
+    let direction = reflect(-view_dir, normal);
+    var reflection = sky_color(direction);
+    if roughness < voxels.reflection_max_roughness && voxels.point_count > 0u {
+        let origin = position + normal * VOXEL_HIT_DISTANCE * 8.0;
+        let t = voxel_march(origin, direction, voxels.reflection_distance);
+        if t >= 0.0 {
+            reflection = reflected_voxel_color(origin + direction * t);
+        }
+    }
+    return mix(color, reflection, weight);
+}
@@ -0,0 +1,91 @@
+// Vertex shader
+
+struct Reprojection {
+    view_proj: mat4x4<f32>,
+    // The inverse of the view projection the frame was rendered with
+    previous_inv_view_proj: mat4x4<f32>,
+    // The camera's viewport in uv coordinates, offset and size
+    viewport: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> reprojection: Reprojection;
+
+@group(1) @binding(0)
+var t_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_color: sampler;
+@group(1) @binding(2)
+var t_depth: texture_depth_2d;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the whole viewport
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = reprojection.viewport.xy + vec2<f32>(uv.x, 1.0 - uv.y) * reprojection.viewport.zw;
+    return out;
+}
+
+// Fragment shader
+
+const ITERATIONS: u32 = 4u;
+
+fn depth_at(uv: vec2<f32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(t_depth));
+    return textureLoad(t_depth, clamp(vec2<i32>(uv * vec2<f32>(size)), vec2<i32>(0), size - 1), 0);
+}
+
+// Where the texel at uv of the rendered frame ends up with the current camera pose
+fn reproject(uv: vec2<f32>) -> vec2<f32> {
+    let local = (uv - reprojection.viewport.xy) / reprojection.viewport.zw;
+    let ndc = vec3<f32>(local.x * 2.0 - 1.0, 1.0 - local.y * 2.0, depth_at(uv));
+    let world = reprojection.previous_inv_view_proj * vec4<f32>(ndc, 1.0);
+    let clip = reprojection.view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    // Points behind the camera can't end up on screen
+    if clip.w <= 0.0 {
+        return vec2<f32>(-1000.0);
+    }
+    let current = clip.xy / clip.w;
+    return reprojection.viewport.xy + vec2<f32>(current.x * 0.5 + 0.5, 0.5 - current.y * 0.5) * reprojection.viewport.zw;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let uv_min = reprojection.viewport.xy + 0.5 / size;
+    let uv_max = reprojection.viewport.xy + reprojection.viewport.zw - 0.5 / size;
+
+    // Search the rendered texel that moved to this pixel, starting at the same position
+    var source = in.uv;
+    for (var i = 0u; i < ITERATIONS; i++) {
+        source = clamp(source - (reproject(source) - in.uv), uv_min, uv_max);
+    }
+
+    // Disocclusions don't have a rendered texel, they are filled with the furthest surface nearby
+    let error = length((reproject(source) - in.uv) * size);
+    if error > 1.0 {
+        let radius = min(error, 16.0) / size;
+        var furthest = depth_at(source);
+        var fill = source;
+        for (var i = 0; i < 8; i++) {
+            let angle = f32(i) * 0.7853982;
+            let candidate = clamp(source + vec2<f32>(cos(angle), sin(angle)) * radius, uv_min, uv_max);
+            let depth = depth_at(candidate);
+            if depth > furthest {
+                furthest = depth;
+                fill = candidate;
+            }
+        }
+        source = fill;
+    }
+
+    return textureSampleLevel(t_color, s_color, source, 0.0);
+}
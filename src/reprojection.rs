@@ -0,0 +1,340 @@
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Vec2};
+
+use crate::{camera::Camera, texture};
+
+/// Settings for reprojection.
+/// When enabled the scene is only rendered at `render_rate`, every call to `render()` in between reprojects the last rendered frame to the current camera poses.
+/// A call to `render()` that renders the scene reprojects it in the same submission, so only the calls in between are cheaper than a full render.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReprojectionSettings {
+    pub enabled: bool,
+    /// How many times per second the scene is rendered.
+    pub render_rate: f32,
+}
+
+impl Default for ReprojectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            render_rate: 30.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ReprojectionUniform {
+    view_projection: [[f32; 4]; 4],
+    previous_inverse_view_projection: [[f32; 4]; 4],
+    viewport: [f32; 4],
+}
+
+/// The bind group layouts of the reprojection pass.
+pub(crate) struct ReprojectionLayouts {
+    pub camera: wgpu::BindGroupLayout,
+    pub source: wgpu::BindGroupLayout,
+}
+
+impl ReprojectionLayouts {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let camera = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("reprojection_camera_bind_group_layout"),
+        });
+        let source = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("reprojection_source_bind_group_layout"),
+        });
+
+        Self { camera, source }
+    }
+}
+
+/// The pose a camera had when the scene was last rendered.
+struct ReprojectionCamera {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Binds the rendered frame together with the depth of this camera.
+    source_bind_group: wgpu::BindGroup,
+    previous_view_projection: Mat4,
+}
+
+/// Holds the last rendered frame and reprojects it to the current camera poses.
+pub(crate) struct Reprojection {
+    pub settings: ReprojectionSettings,
+    color_view: wgpu::TextureView,
+    /// The size of the frame textures in pixels.
+    size: (f32, f32),
+    sampler: wgpu::Sampler,
+    cameras: Vec<Option<ReprojectionCamera>>,
+    last_render: Option<Instant>,
+}
+
+impl Reprojection {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let color_view = create_color_view(device, config);
+
+        Self {
+            settings: ReprojectionSettings::default(),
+            color_view,
+            size: (config.width as f32, config.height as f32),
+            sampler,
+            cameras: vec![],
+            last_render: None,
+        }
+    }
+
+    /// Recreates the frame texture, the next frame has to render the scene again.
+    /// The cameras are dropped as well, since they bind the depth textures that were recreated with the new size.
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.color_view = create_color_view(device, config);
+        self.size = (config.width as f32, config.height as f32);
+        self.cameras.clear();
+        self.last_render = None;
+    }
+
+    /// The texture the scene is rendered into.
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+
+    /// Whether the scene has to be rendered this frame, either because it is due or a camera has no rendered frame yet.
+    pub fn needs_render(&self, cameras: &[Option<Camera>]) -> bool {
+        let interval = Duration::from_secs_f32(1.0 / self.settings.render_rate.max(0.001));
+        let due = self
+            .last_render
+            .is_none_or(|last_render| last_render.elapsed() >= interval);
+        let missing_camera = cameras.iter().enumerate().any(|(index, camera)| {
+            camera.is_some() && self.cameras.get(index).is_none_or(Option::is_none)
+        });
+
+        due || missing_camera
+    }
+
+    /// Remembers the poses the scene was rendered with and the depth textures it was rendered into.
+    pub fn store_poses(
+        &mut self,
+        device: &wgpu::Device,
+        layouts: &ReprojectionLayouts,
+        cameras: &[Option<Camera>],
+        lens_shifts: &[Vec2],
+        depth_textures: &[Option<texture::Texture>],
+    ) {
+        let aspect = self.size.0 / self.size.1;
+        self.cameras.resize_with(cameras.len(), || None);
+        for (((camera, lens_shift), depth_texture), target) in cameras
+            .iter()
+            .zip(lens_shifts)
+            .zip(depth_textures)
+            .zip(&mut self.cameras)
+        {
+            let (Some(camera), Some(depth_texture)) = (camera, depth_texture) else {
+                *target = None;
+                continue;
+            };
+            let view_projection = camera.build_shifted_view_projection_matrix(aspect, *lens_shift);
+            match target {
+                Some(target) => target.previous_view_projection = view_projection,
+                None => {
+                    let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Reprojection Uniform Buffer"),
+                        size: std::mem::size_of::<ReprojectionUniform>() as wgpu::BufferAddress,
+                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    });
+                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        layout: &layouts.camera,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform_buffer.as_entire_binding(),
+                        }],
+                        label: Some("reprojection_camera_bind_group"),
+                    });
+                    let source_bind_group = create_source_bind_group(
+                        device,
+                        layouts,
+                        &self.color_view,
+                        &self.sampler,
+                        depth_texture,
+                    );
+                    *target = Some(ReprojectionCamera {
+                        uniform_buffer,
+                        bind_group,
+                        source_bind_group,
+                        previous_view_projection: view_projection,
+                    });
+                }
+            }
+        }
+        self.last_render = Some(Instant::now());
+    }
+
+    /// Reprojects the last rendered frame of every camera to its current pose.
+    pub fn reproject(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        cameras: &[Option<Camera>],
+        lens_shifts: &[Vec2],
+    ) {
+        let (width, height) = self.size;
+        let aspect = width / height;
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Reprojection Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.1,
+                        b: 0.1,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+
+        for ((camera, lens_shift), target) in cameras.iter().zip(lens_shifts).zip(&self.cameras) {
+            let (Some(camera), Some(target)) = (camera, target) else {
+                continue;
+            };
+
+            let viewport = match &camera.viewport {
+                Some(viewport) => [
+                    viewport.x / width,
+                    viewport.y / height,
+                    viewport.w / width,
+                    viewport.h / height,
+                ],
+                None => [0.0, 0.0, 1.0, 1.0],
+            };
+            let uniform = ReprojectionUniform {
+                view_projection: camera
+                    .build_shifted_view_projection_matrix(aspect, *lens_shift)
+                    .to_cols_array_2d(),
+                previous_inverse_view_projection: target
+                    .previous_view_projection
+                    .inverse()
+                    .to_cols_array_2d(),
+                viewport,
+            };
+            queue.write_buffer(&target.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+            render_pass.set_viewport(
+                viewport[0] * width,
+                viewport[1] * height,
+                viewport[2] * width,
+                viewport[3] * height,
+                0.0,
+                1.0,
+            );
+            render_pass.set_bind_group(0, &target.bind_group, &[]);
+            render_pass.set_bind_group(1, &target.source_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_color_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("reprojection_color_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_source_bind_group(
+    device: &wgpu::Device,
+    layouts: &ReprojectionLayouts,
+    color_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    depth_texture: &texture::Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &layouts.source,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(color_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+            },
+        ],
+        label: Some("reprojection_source_bind_group"),
+    })
+}
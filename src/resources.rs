@@ -1,9 +1,22 @@
-use std::io::{self, BufReader, Cursor};
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Cursor},
+    sync::{Arc, Weak},
+};
 
-use image::ImageError;
+use anyhow::anyhow;
+
+use glam::Vec3;
 use wgpu::util::DeviceExt;
 
-use crate::{instance::Instance, model, texture};
+use crate::{
+    buffer,
+    culling::Aabb,
+    instance::Instance,
+    model,
+    texture::{self, TextureSlot},
+    voxel::{VoxelPoint, VoxelVolume},
+};
 
 pub async fn load_string(file_name: &str) -> Result<String, io::Error> {
     let path = std::path::Path::new(env!("OUT_DIR"))
@@ -23,28 +36,73 @@ pub async fn load_binary(file_name: &str) -> Result<Vec<u8>, io::Error> {
     Ok(data)
 }
 
+/// The `res/` directory of the crate, which the build script copies next to the build output.
+/// This is the path of the checkout the crate was built from, it only exists while running from that checkout.
+pub fn source_path(file_name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("res")
+        .join(file_name)
+}
+
+/// Copies a changed resource from the `res/` directory over the copy resources are loaded from, like a rebuild would.
+pub fn refresh_resource(file_name: &str) -> Result<(), io::Error> {
+    let path = std::path::Path::new(env!("OUT_DIR"))
+        .join("res")
+        .join(file_name);
+    std::fs::copy(source_path(file_name), path)?;
+
+    Ok(())
+}
+
+/// The `.mtl` files an `.obj` file references.
+async fn material_libraries(file_name: &str) -> Result<Vec<String>, io::Error> {
+    let obj_text = load_string(file_name).await?;
+    Ok(obj_text
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .flat_map(str::split_whitespace)
+        .map(String::from)
+        .collect())
+}
+
 pub async fn load_texture(
     file_name: &str,
     is_normal_map: bool,
+    sampler: Arc<wgpu::Sampler>,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> Result<texture::Texture, ImageError> {
+) -> anyhow::Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("ktx2" | "dds") => texture::Texture::from_compressed(
+            device,
+            queue,
+            &data,
+            file_name,
+            is_normal_map,
+            sampler,
+        ),
+        _ => Ok(texture::Texture::from_bytes(
+            device,
+            queue,
+            &data,
+            file_name,
+            is_normal_map,
+            sampler,
+        )?),
+    }
 }
 
-pub async fn load_model(
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
-    instances: Vec<Instance>,
-) -> anyhow::Result<model::Model> {
+async fn load_obj(file_name: &str) -> anyhow::Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
+    let (models, materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
             triangulate: true,
@@ -58,19 +116,219 @@ pub async fn load_model(
     )
     .await?;
 
-    let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture =
-            load_texture(&m.diffuse_texture.unwrap(), false, device, queue).await?; // UDPATED!
-        let normal_texture = load_texture(&m.normal_texture.unwrap(), true, device, queue).await?; // UPDATED!
+    Ok((models, materials?))
+}
+
+/// Converts the Blinn-Phong exponent to a perceptual roughness
+fn shininess_to_roughness(shininess: Option<f32>) -> f32 {
+    shininess.map_or(1.0, |shininess| {
+        (2.0 / (shininess.max(0.0) + 2.0)).powf(0.25)
+    })
+}
+
+/// Shares the textures and materials of models that reference the same files.
+/// Only weak references are kept, so the assets are freed together with the last model using them.
+#[derive(Default)]
+pub struct AssetCache {
+    textures: HashMap<TextureKey, Weak<texture::Texture>>,
+    materials: HashMap<MaterialKey, Weak<model::Material>>,
+}
+
+/// Textures are only shared between models sampling them the same way, the sampler is compared by address.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TextureKey {
+    file_name: String,
+    is_normal_map: bool,
+    sampler: usize,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct MaterialKey {
+    name: String,
+    diffuse_texture: TextureKey,
+    normal_texture: TextureKey,
+    roughness: u32,
+}
 
-        materials.push(model::Material::new(
+impl AssetCache {
+    async fn texture(
+        &mut self,
+        key: TextureKey,
+        sampler: &Arc<wgpu::Sampler>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<Arc<texture::Texture>> {
+        if let Some(texture) = self.textures.get(&key).and_then(Weak::upgrade) {
+            return Ok(texture);
+        }
+
+        let texture = Arc::new(
+            load_texture(
+                &key.file_name,
+                key.is_normal_map,
+                sampler.clone(),
+                device,
+                queue,
+            )
+            .await?,
+        );
+        self.textures.insert(key, Arc::downgrade(&texture));
+        Ok(texture)
+    }
+
+    async fn material(
+        &mut self,
+        material: &tobj::Material,
+        diffuse_sampler: &Arc<wgpu::Sampler>,
+        normal_sampler: &Arc<wgpu::Sampler>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Arc<model::Material>> {
+        let texture_key = |file_name: &Option<String>, is_normal_map, sampler| {
+            Ok::<_, anyhow::Error>(TextureKey {
+                file_name: file_name
+                    .clone()
+                    .ok_or_else(|| anyhow!("material {} is missing a texture", material.name))?,
+                is_normal_map,
+                sampler: Arc::as_ptr(sampler) as usize,
+            })
+        };
+        let diffuse_key = texture_key(&material.diffuse_texture, false, diffuse_sampler)?;
+        let normal_key = texture_key(&material.normal_texture, true, normal_sampler)?;
+        let roughness = shininess_to_roughness(material.shininess);
+        let key = MaterialKey {
+            name: material.name.clone(),
+            diffuse_texture: diffuse_key.clone(),
+            normal_texture: normal_key.clone(),
+            roughness: roughness.to_bits(),
+        };
+        if let Some(material) = self.materials.get(&key).and_then(Weak::upgrade) {
+            return Ok(material);
+        }
+
+        let diffuse_texture = self
+            .texture(diffuse_key, diffuse_sampler, device, queue)
+            .await?;
+        let normal_texture = self
+            .texture(normal_key, normal_sampler, device, queue)
+            .await?;
+        let material = Arc::new(model::Material::new(
             device,
-            &m.name,
+            &material.name,
             diffuse_texture,
             normal_texture,
+            roughness,
             layout,
-        ));
+        )?);
+        self.materials.insert(key, Arc::downgrade(&material));
+        Ok(material)
+    }
+
+    /// Loads a changed texture file again and rebuilds every material using it.
+    /// Returns the previous materials together with the ones replacing them.
+    pub async fn reload_texture(
+        &mut self,
+        file_name: &str,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Vec<(Arc<model::Material>, Arc<model::Material>)>> {
+        let mut reloaded = HashMap::new();
+        for (key, texture) in &self.textures {
+            let Some(texture) = texture.upgrade() else {
+                continue;
+            };
+            if key.file_name == file_name {
+                let sampler = texture.sampler.clone();
+                let texture =
+                    load_texture(file_name, key.is_normal_map, sampler, device, queue).await?;
+                reloaded.insert(key.clone(), Arc::new(texture));
+            }
+        }
+
+        let mut replaced = vec![];
+        for (key, material) in &mut self.materials {
+            let Some(previous) = material.upgrade() else {
+                continue;
+            };
+            let diffuse_texture = reloaded.get(&key.diffuse_texture);
+            let normal_texture = reloaded.get(&key.normal_texture);
+            if diffuse_texture.is_none() && normal_texture.is_none() {
+                continue;
+            }
+
+            let new = Arc::new(model::Material::new(
+                device,
+                &previous.name,
+                diffuse_texture.unwrap_or(&previous.diffuse_texture).clone(),
+                normal_texture.unwrap_or(&previous.normal_texture).clone(),
+                previous.roughness,
+                layout,
+            )?);
+            *material = Arc::downgrade(&new);
+            replaced.push((previous, new));
+        }
+        for (key, texture) in &reloaded {
+            self.textures.insert(key.clone(), Arc::downgrade(texture));
+        }
+
+        Ok(replaced)
+    }
+
+    /// The files of the textures that are currently in use.
+    pub fn texture_files(&self) -> impl Iterator<Item = &str> {
+        self.textures
+            .iter()
+            .filter(|(_, texture)| texture.strong_count() > 0)
+            .map(|(key, _)| key.file_name.as_str())
+    }
+
+    /// Forgets the assets no model uses anymore.
+    pub fn prune(&mut self) {
+        self.textures
+            .retain(|_, texture| texture.strong_count() > 0);
+        self.materials
+            .retain(|_, material| material.strong_count() > 0);
+    }
+
+    /// The number of textures that are currently in use.
+    pub fn texture_count(&self) -> usize {
+        self.textures
+            .values()
+            .filter(|texture| texture.strong_count() > 0)
+            .count()
+    }
+
+    /// The number of materials that are currently in use.
+    pub fn material_count(&self) -> usize {
+        self.materials
+            .values()
+            .filter(|material| material.strong_count() > 0)
+            .count()
+    }
+}
+
+pub async fn load_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    instances: Vec<Instance>,
+    mut sampler: impl FnMut(&str, TextureSlot) -> Arc<wgpu::Sampler>,
+    cache: &mut AssetCache,
+) -> anyhow::Result<model::Model> {
+    let (models, obj_materials) = load_obj(file_name).await?;
+
+    let mut materials = Vec::new();
+    for m in &obj_materials {
+        let diffuse_sampler = sampler(&m.name, TextureSlot::Diffuse);
+        let normal_sampler = sampler(&m.name, TextureSlot::Normal);
+        materials.push(
+            cache
+                .material(m, &diffuse_sampler, &normal_sampler, device, queue, layout)
+                .await?,
+        );
     }
 
     let meshes = models
@@ -166,27 +424,690 @@ pub async fn load_model(
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
                 contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             });
 
+            let bounds =
+                Aabb::from_points(vertices.iter().map(|v| v.position.into())).unwrap_or_default();
+
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                bounds,
             }
         })
         .collect::<Vec<_>>();
 
+    let bounds = meshes
+        .iter()
+        .map(|mesh| mesh.bounds)
+        .reduce(|a, b| a.union(&b))
+        .unwrap_or_default();
+
     Ok(model::Model {
+        file_name: file_name.to_string(),
+        material_libraries: material_libraries(file_name).await?,
         meshes,
         materials,
         instances,
+        bounds,
     })
 }
+
+/// Loads a MagicaVoxel `.vox` file into a single [`VoxelVolume`], placing its models like the scene graph of the file does.
+/// `voxel_size` is the edge length of a voxel in world units. MagicaVoxel is z-up, the volume is converted to y-up.
+pub async fn load_vox(
+    file_name: &str,
+    voxel_size: f32,
+    hollow: bool,
+) -> anyhow::Result<VoxelVolume> {
+    anyhow::ensure!(voxel_size > 0.0, "the voxel size has to be positive");
+
+    let data = load_binary(file_name).await?;
+    let scene = vox::parse(&data)?;
+
+    // Only the palette entries in use become materials
+    let mut material_ids = [None; 256];
+    let mut materials = Vec::new();
+    let mut points = Vec::new();
+    // Large enough to cover the whole cube of the voxel
+    let radius = voxel_size * 0.5 * 3.0_f32.sqrt();
+
+    for (model_id, transform) in scene.instances() {
+        let Some(model) = scene.models.get(model_id) else {
+            continue;
+        };
+        let half_size = model.size.as_vec3() * 0.5;
+        for &(position, color) in &model.voxels {
+            if hollow && model.is_hidden(position) {
+                continue;
+            }
+
+            let material = *material_ids[color as usize].get_or_insert_with(|| {
+                materials.push(scene.material(color));
+                materials.len() as u32 - 1
+            });
+            let local = position.as_vec3() + 0.5 - half_size;
+            let world = transform.apply(local);
+            points.push(VoxelPoint {
+                position: Vec3::new(world.x, world.z, -world.y) * voxel_size,
+                radius,
+                material,
+            });
+        }
+    }
+
+    Ok(VoxelVolume::new(points, materials))
+}
+
+/// Voxelizes the meshes of a loaded model into a [`VoxelVolume`] in model space.
+/// Every voxel the surface passes through becomes a point, colored by the diffuse texture of its material.
+/// The vertices and textures are read back from the GPU, so this waits until all submitted work is done.
+/// `voxel_size` is the edge length of a voxel in world units.
+pub fn voxelize_model(
+    model: &model::Model,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    voxel_size: f32,
+) -> anyhow::Result<VoxelVolume> {
+    anyhow::ensure!(voxel_size > 0.0, "the voxel size has to be positive");
+
+    let materials = model
+        .materials
+        .iter()
+        .map(|m| {
+            Ok(voxelizer::SurfaceMaterial {
+                diffuse_texture: m.diffuse_texture.read_rgba(device, queue)?,
+                roughness: m.roughness,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut voxelizer = voxelizer::Voxelizer::new(voxel_size);
+    for mesh in &model.meshes {
+        let vertices: Vec<model::ModelVertex> =
+            bytemuck::pod_collect_to_vec(&buffer::read_buffer(device, queue, &mesh.vertex_buffer)?);
+        let indices: Vec<u32> =
+            bytemuck::pod_collect_to_vec(&buffer::read_buffer(device, queue, &mesh.index_buffer)?);
+        let material = materials.get(mesh.material);
+
+        for c in indices[..mesh.num_elements as usize].chunks_exact(3) {
+            let [v0, v1, v2] = [0, 1, 2].map(|i| vertices[c[i] as usize]);
+            let triangle = [v0, v1, v2].map(|v| Vec3::from(v.position));
+            let uvs = [v0, v1, v2].map(|v| glam::Vec2::from(v.tex_coords));
+            voxelizer.add_triangle(triangle, material.map(|_| mesh.material), |barycentric| {
+                let Some(material) = material else {
+                    return Vec3::ONE;
+                };
+                material
+                    .color(uvs[0] * barycentric.x + uvs[1] * barycentric.y + uvs[2] * barycentric.z)
+            });
+        }
+    }
+
+    Ok(voxelizer.finish(|material| {
+        material
+            .and_then(|id| materials.get(id))
+            .map_or(1.0, |material| material.roughness)
+    }))
+}
+
+/// Conservative voxelization of triangles
+mod voxelizer {
+    use std::collections::HashMap;
+
+    use glam::{IVec3, Vec2, Vec3};
+
+    use crate::{
+        texture::srgb_to_linear,
+        voxel::{VoxelMaterial, VoxelPoint, VoxelVolume},
+    };
+
+    /// The parts of a material that determine the voxel colors.
+    pub struct SurfaceMaterial {
+        pub diffuse_texture: image::RgbaImage,
+        pub roughness: f32,
+    }
+
+    impl SurfaceMaterial {
+        /// The linear color at the texture coordinates, which are clamped to the edge like the default sampler does.
+        pub fn color(&self, uv: Vec2) -> Vec3 {
+            let texture = &self.diffuse_texture;
+            let (width, height) = texture.dimensions();
+            let x = (uv.x.clamp(0.0, 1.0) * width as f32) as u32;
+            let y = (uv.y.clamp(0.0, 1.0) * height as f32) as u32;
+            let [r, g, b, _] = texture.get_pixel(x.min(width - 1), y.min(height - 1)).0;
+            Vec3::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b))
+        }
+    }
+
+    /// The colors of all triangles passing through a voxel are averaged.
+    struct VoxelSample {
+        color_sum: Vec3,
+        count: u32,
+        material: Option<usize>,
+    }
+
+    pub struct Voxelizer {
+        voxel_size: f32,
+        voxels: HashMap<IVec3, VoxelSample>,
+    }
+
+    impl Voxelizer {
+        pub fn new(voxel_size: f32) -> Self {
+            Self {
+                voxel_size,
+                voxels: HashMap::new(),
+            }
+        }
+
+        /// Marks every voxel the triangle overlaps. `color` is called with the barycentric coordinates of the point on the triangle closest to the voxel center.
+        pub fn add_triangle(
+            &mut self,
+            triangle: [Vec3; 3],
+            material: Option<usize>,
+            mut color: impl FnMut(Vec3) -> Vec3,
+        ) {
+            let min = triangle[0].min(triangle[1]).min(triangle[2]);
+            let max = triangle[0].max(triangle[1]).max(triangle[2]);
+            let cell_min = (min / self.voxel_size).floor().as_ivec3();
+            let cell_max = (max / self.voxel_size).floor().as_ivec3();
+            let half_size = self.voxel_size * 0.5;
+
+            for z in cell_min.z..=cell_max.z {
+                for y in cell_min.y..=cell_max.y {
+                    for x in cell_min.x..=cell_max.x {
+                        let cell = IVec3::new(x, y, z);
+                        let center = (cell.as_vec3() + 0.5) * self.voxel_size;
+                        if !overlaps_box(triangle.map(|v| v - center), half_size) {
+                            continue;
+                        }
+
+                        let sample = self.voxels.entry(cell).or_insert(VoxelSample {
+                            color_sum: Vec3::ZERO,
+                            count: 0,
+                            material,
+                        });
+                        sample.color_sum += color(closest_barycentric(center, triangle));
+                        sample.count += 1;
+                    }
+                }
+            }
+        }
+
+        /// Turns the voxels into points. Materials are shared by voxels with the same source material and a similar color.
+        pub fn finish(self, roughness: impl Fn(Option<usize>) -> f32) -> VoxelVolume {
+            let mut voxels = self.voxels.into_iter().collect::<Vec<_>>();
+            voxels.sort_unstable_by_key(|(cell, _)| cell.to_array());
+
+            let mut material_ids = HashMap::new();
+            let mut materials = Vec::new();
+            // Large enough to cover the whole cube of the voxel
+            let radius = self.voxel_size * 0.5 * 3.0_f32.sqrt();
+            let points = voxels
+                .into_iter()
+                .map(|(cell, sample)| {
+                    let color = sample.color_sum / sample.count as f32;
+                    // Quantized to 6 bits per channel
+                    let key = (sample.material, (color * 63.0).round().as_ivec3());
+                    let material = *material_ids.entry(key).or_insert_with(|| {
+                        materials.push(VoxelMaterial {
+                            color: key.1.as_vec3() / 63.0,
+                            roughness: roughness(sample.material),
+                            ..Default::default()
+                        });
+                        materials.len() as u32 - 1
+                    });
+                    VoxelPoint {
+                        position: (cell.as_vec3() + 0.5) * self.voxel_size,
+                        radius,
+                        material,
+                    }
+                })
+                .collect();
+
+            VoxelVolume::new(points, materials)
+        }
+    }
+
+    /// Separating axis test between a triangle and an axis aligned box centered at the origin.
+    fn overlaps_box(triangle: [Vec3; 3], half_size: f32) -> bool {
+        let [v0, v1, v2] = triangle;
+
+        // The axes of the box
+        if v0.min(v1).min(v2).max_element() > half_size
+            || v0.max(v1).max(v2).min_element() < -half_size
+        {
+            return false;
+        }
+
+        // The plane of the triangle
+        let normal = (v1 - v0).cross(v2 - v1);
+        if normal.dot(v0).abs() > half_size * normal.abs().dot(Vec3::ONE) {
+            return false;
+        }
+
+        // The cross products of the box axes and the triangle edges
+        for edge in [v1 - v0, v2 - v1, v0 - v2] {
+            for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                let axis = axis.cross(edge);
+                let projections = Vec3::new(axis.dot(v0), axis.dot(v1), axis.dot(v2));
+                let radius = half_size * axis.abs().dot(Vec3::ONE);
+                if projections.min_element() > radius || projections.max_element() < -radius {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// The barycentric coordinates of the point on the triangle closest to `p`.
+    fn closest_barycentric(p: Vec3, [a, b, c]: [Vec3; 3]) -> Vec3 {
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return Vec3::X;
+        }
+
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return Vec3::Y;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            let v = d1 / (d1 - d3);
+            return Vec3::new(1.0 - v, v, 0.0);
+        }
+
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return Vec3::Z;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            let w = d2 / (d2 - d6);
+            return Vec3::new(1.0 - w, 0.0, w);
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return Vec3::new(0.0, 1.0 - w, w);
+        }
+
+        let denominator = va + vb + vc;
+        // Degenerate triangles
+        if denominator.abs() <= f32::EPSILON {
+            return Vec3::X;
+        }
+        let v = vb / denominator;
+        let w = vc / denominator;
+        Vec3::new(1.0 - v - w, v, w)
+    }
+}
+
+/// Parsing of the MagicaVoxel file format, see <https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt>
+mod vox {
+    use std::collections::{HashMap, HashSet};
+
+    use anyhow::{bail, ensure, Context};
+    use glam::{IVec3, Mat3, UVec3, Vec3};
+
+    use crate::{texture::srgb_to_linear, voxel::VoxelMaterial};
+
+    pub struct VoxModel {
+        pub size: UVec3,
+        pub voxels: Vec<(UVec3, u8)>,
+        occupied: HashSet<UVec3>,
+    }
+
+    impl VoxModel {
+        /// Whether all six neighbours of the voxel are filled, which hides it completely.
+        pub fn is_hidden(&self, position: UVec3) -> bool {
+            let position = position.as_ivec3();
+            [
+                IVec3::X,
+                IVec3::NEG_X,
+                IVec3::Y,
+                IVec3::NEG_Y,
+                IVec3::Z,
+                IVec3::NEG_Z,
+            ]
+            .iter()
+            .all(|offset| {
+                let neighbour = position + *offset;
+                neighbour.min_element() >= 0 && self.occupied.contains(&neighbour.as_uvec3())
+            })
+        }
+    }
+
+    /// A rotation and translation of the scene graph.
+    #[derive(Clone, Copy)]
+    pub struct Transform {
+        rotation: Mat3,
+        translation: Vec3,
+    }
+
+    impl Transform {
+        const IDENTITY: Self = Self {
+            rotation: Mat3::IDENTITY,
+            translation: Vec3::ZERO,
+        };
+
+        pub fn apply(&self, position: Vec3) -> Vec3 {
+            self.rotation * position + self.translation
+        }
+
+        fn then(&self, child: &Self) -> Self {
+            Self {
+                rotation: self.rotation * child.rotation,
+                translation: self.apply(child.translation),
+            }
+        }
+    }
+
+    enum Node {
+        Transform { child: i32, transform: Transform },
+        Group { children: Vec<i32> },
+        Shape { models: Vec<usize> },
+    }
+
+    pub struct VoxScene {
+        pub models: Vec<VoxModel>,
+        palette: [[u8; 4]; 256],
+        materials: HashMap<u8, VoxelMaterial>,
+        nodes: HashMap<i32, Node>,
+    }
+
+    impl VoxScene {
+        /// Every model placed in the scene with its transform. Files without a scene graph place all models at the origin.
+        pub fn instances(&self) -> Vec<(usize, Transform)> {
+            let mut instances = Vec::new();
+            if self.nodes.contains_key(&0) {
+                self.visit(0, Transform::IDENTITY, &mut instances, 0);
+            } else {
+                instances.extend((0..self.models.len()).map(|id| (id, Transform::IDENTITY)));
+            }
+            instances
+        }
+
+        fn visit(
+            &self,
+            node: i32,
+            transform: Transform,
+            instances: &mut Vec<(usize, Transform)>,
+            depth: u32,
+        ) {
+            // Guards against cycles in malformed files
+            if depth > 64 {
+                return;
+            }
+            match self.nodes.get(&node) {
+                Some(Node::Transform {
+                    child,
+                    transform: local,
+                }) => self.visit(*child, transform.then(local), instances, depth + 1),
+                Some(Node::Group { children }) => {
+                    for child in children {
+                        self.visit(*child, transform, instances, depth + 1);
+                    }
+                }
+                Some(Node::Shape { models }) => {
+                    instances.extend(models.iter().map(|model| (*model, transform)));
+                }
+                None => {}
+            }
+        }
+
+        /// The material of a palette index, colors are converted from sRGB to linear.
+        pub fn material(&self, color: u8) -> VoxelMaterial {
+            let [r, g, b, _] = self.palette[color as usize];
+            VoxelMaterial {
+                color: Vec3::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)),
+                ..self.materials.get(&color).copied().unwrap_or_default()
+            }
+        }
+    }
+
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+            ensure!(self.data.len() >= len, "unexpected end of .vox data");
+            let (bytes, rest) = self.data.split_at(len);
+            self.data = rest;
+            Ok(bytes)
+        }
+
+        fn i32(&mut self) -> anyhow::Result<i32> {
+            Ok(i32::from_le_bytes(self.bytes(4)?.try_into()?))
+        }
+
+        fn len(&mut self) -> anyhow::Result<usize> {
+            usize::try_from(self.i32()?).context("negative length in .vox data")
+        }
+
+        fn string(&mut self) -> anyhow::Result<String> {
+            let len = self.len()?;
+            Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+        }
+
+        fn dict(&mut self) -> anyhow::Result<HashMap<String, String>> {
+            (0..self.len()?)
+                .map(|_| Ok((self.string()?, self.string()?)))
+                .collect()
+        }
+    }
+
+    /// The default palette used by files without an `RGBA` chunk.
+    /// A 6x6x6 color cube without black followed by ramps of red, green, blue and gray.
+    fn default_palette() -> [[u8; 4]; 256] {
+        const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+        const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+        let mut palette = [[0; 4]; 256];
+        let cube = CUBE.iter().flat_map(|r| {
+            CUBE.iter()
+                .flat_map(move |g| CUBE.iter().map(move |b| [*r, *g, *b, 0xff]))
+        });
+        let ramps = (0..4).flat_map(|channel| {
+            RAMP.iter().map(move |value| {
+                let mut color = [0, 0, 0, 0xff];
+                match channel {
+                    3 => color[..3].fill(*value),
+                    _ => color[channel] = *value,
+                }
+                color
+            })
+        });
+        for (entry, color) in palette[1..].iter_mut().zip(cube.take(215).chain(ramps)) {
+            *entry = color;
+        }
+        palette
+    }
+
+    /// Decodes the rotation of a transform frame, a permutation matrix with signs packed into a byte.
+    fn rotation(packed: u8) -> Mat3 {
+        let first = (packed & 3) as usize;
+        let second = ((packed >> 2) & 3) as usize;
+        let third = 3usize.saturating_sub(first + second);
+        let mut rows = [Vec3::ZERO; 3];
+        for (row, (index, sign_bit)) in rows.iter_mut().zip([(first, 4), (second, 5), (third, 6)]) {
+            let sign = if packed & (1 << sign_bit) != 0 {
+                -1.0
+            } else {
+                1.0
+            };
+            if index < 3 {
+                row[index] = sign;
+            }
+        }
+        Mat3::from_cols(rows[0], rows[1], rows[2]).transpose()
+    }
+
+    fn parse_transform(frame: &HashMap<String, String>) -> anyhow::Result<Transform> {
+        let rotation = match frame.get("_r") {
+            Some(r) => rotation(r.parse()?),
+            None => Mat3::IDENTITY,
+        };
+        let translation = match frame.get("_t") {
+            Some(t) => {
+                let values = t
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<i32>, _>>()?;
+                ensure!(values.len() == 3, "invalid translation {t:?} in .vox data");
+                IVec3::from_slice(&values).as_vec3()
+            }
+            None => Vec3::ZERO,
+        };
+        Ok(Transform {
+            rotation,
+            translation,
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<VoxScene> {
+        let mut reader = Reader { data };
+        ensure!(reader.bytes(4)? == b"VOX ", "not a .vox file");
+        let _version = reader.i32()?;
+        ensure!(
+            reader.bytes(4)? == b"MAIN",
+            "missing MAIN chunk in .vox file"
+        );
+        let _content_size = reader.len()?;
+        let _children_size = reader.len()?;
+
+        let mut scene = VoxScene {
+            models: Vec::new(),
+            palette: default_palette(),
+            materials: HashMap::new(),
+            nodes: HashMap::new(),
+        };
+        let mut size = None;
+
+        while !reader.data.is_empty() {
+            let id: [u8; 4] = reader.bytes(4)?.try_into()?;
+            let content_size = reader.len()?;
+            let children_size = reader.len()?;
+            let mut content = Reader {
+                data: reader.bytes(content_size)?,
+            };
+            // Only MAIN has children
+            reader.bytes(children_size)?;
+
+            match &id {
+                b"SIZE" => {
+                    let [x, y, z] = [content.len()?, content.len()?, content.len()?];
+                    size = Some(UVec3::new(x as u32, y as u32, z as u32));
+                }
+                b"XYZI" => {
+                    let Some(size) = size.take() else {
+                        bail!("XYZI chunk without SIZE chunk in .vox file");
+                    };
+                    let voxels = (0..content.len()?)
+                        .map(|_| {
+                            let [x, y, z, color] = content.bytes(4)?.try_into()?;
+                            Ok((UVec3::new(x as u32, y as u32, z as u32), color))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let occupied = voxels.iter().map(|(position, _)| *position).collect();
+                    scene.models.push(VoxModel {
+                        size,
+                        voxels,
+                        occupied,
+                    });
+                }
+                // Color i of the chunk is palette index i + 1
+                b"RGBA" => {
+                    for entry in &mut scene.palette[1..] {
+                        *entry = content.bytes(4)?.try_into()?;
+                    }
+                }
+                b"MATL" => {
+                    let id = content.i32()?;
+                    let properties = content.dict()?;
+                    let property = |key: &str| properties.get(key).and_then(|v| v.parse().ok());
+                    let roughness = property("_rough").unwrap_or(1.0);
+                    let emission = match properties.get("_type").map(String::as_str) {
+                        Some("_emit") => {
+                            // _flux is a power of two boost on top of the emission
+                            property("_emit").unwrap_or(0.0)
+                                * 2.0_f32.powf(property("_flux").unwrap_or(0.0))
+                        }
+                        _ => 0.0,
+                    };
+                    if let Ok(color) = u8::try_from(id) {
+                        scene.materials.insert(
+                            color,
+                            VoxelMaterial {
+                                roughness,
+                                emission,
+                                ..Default::default()
+                            },
+                        );
+                    }
+                }
+                b"nTRN" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let child = content.i32()?;
+                    let _reserved = content.i32()?;
+                    let _layer = content.i32()?;
+                    let frames = (0..content.len()?)
+                        .map(|_| content.dict())
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let transform = match frames.first() {
+                        Some(frame) => parse_transform(frame)?,
+                        None => Transform::IDENTITY,
+                    };
+                    scene.nodes.insert(id, Node::Transform { child, transform });
+                }
+                b"nGRP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let children = (0..content.len()?)
+                        .map(|_| content.i32())
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    scene.nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.i32()?;
+                    let _attributes = content.dict()?;
+                    let models = (0..content.len()?)
+                        .map(|_| {
+                            let model = content.len()?;
+                            let _attributes = content.dict()?;
+                            Ok(model)
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    scene.nodes.insert(id, Node::Shape { models });
+                }
+                // Layers, cameras, render settings and notes don't affect the voxels
+                _ => {}
+            }
+        }
+
+        Ok(scene)
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// A shader file together with the defines it is compiled with, every key is compiled into its own variant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub file_name: String,
+    pub defines: BTreeSet<String>,
+}
+
+impl ShaderKey {
+    pub fn new<S: Into<String>>(file_name: &str, defines: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            defines: defines.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&str> for ShaderKey {
+    fn from(file_name: &str) -> Self {
+        Self::new(file_name, [] as [String; 0])
+    }
+}
+
+/// The WGSL source of a [`ShaderKey`] after preprocessing.
+#[derive(Clone, Debug)]
+pub struct ComposedShader {
+    pub source: String,
+    /// Every file the source was composed from, starting with the file of the key.
+    pub files: Vec<String>,
+}
+
+/// A shader file that couldn't be read or preprocessed.
+#[derive(Clone, Debug)]
+pub struct ShaderError {
+    pub file_name: String,
+    /// The line of the offending directive, starting at 1.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file_name, line, self.message),
+            None => write!(f, "{}: {}", self.file_name, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// Loads WGSL files, preprocesses them and caches the compiled variants.
+///
+/// Files are looked up in the sources added with `add_source()`, then in the directory set with `set_dir()`
+/// and finally in the shaders built into the crate. The preprocessor understands these directives at the start of a line:
+/// - `#include "file.wgsl"` inserts a file, every file is only inserted once per shader
+/// - `#define NAME` defines a flag for the rest of the shader
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the lines in between
+#[derive(Default)]
+pub struct ShaderLoader {
+    dir: Option<PathBuf>,
+    sources: HashMap<String, String>,
+    modules: HashMap<ShaderKey, (Arc<wgpu::ShaderModule>, Vec<String>)>,
+}
+
+impl ShaderLoader {
+    /// Reads files from the directory before falling back to the built-in shaders, `None` only uses the built-in shaders.
+    pub fn set_dir(&mut self, dir: Option<PathBuf>) {
+        self.dir = dir;
+        self.modules.clear();
+    }
+
+    pub fn dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref()
+    }
+
+    /// Adds a file that can be compiled or included by other files, replacing files with the same name.
+    pub fn add_source(&mut self, file_name: &str, source: impl Into<String>) {
+        self.sources.insert(file_name.to_string(), source.into());
+        self.invalidate(file_name);
+    }
+
+    /// Drops the cached variants composed from the file, they are compiled again the next time they are used.
+    pub fn invalidate(&mut self, file_name: &str) {
+        self.modules
+            .retain(|_, (_, files)| !files.iter().any(|file| file == file_name));
+    }
+
+    /// The number of cached shader variants.
+    pub fn module_count(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// The files a cached variant was composed from.
+    pub fn files(&self, key: &ShaderKey) -> Option<&[String]> {
+        self.modules.get(key).map(|(_, files)| &files[..])
+    }
+
+    /// The compiled variant of the key, compiled on first use.
+    pub fn module(
+        &mut self,
+        device: &wgpu::Device,
+        key: &ShaderKey,
+    ) -> Result<Arc<wgpu::ShaderModule>, ShaderError> {
+        if let Some((module, _)) = self.modules.get(key) {
+            return Ok(module.clone());
+        }
+
+        let composed = self.compose(key)?;
+        let module = Arc::new(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&key.file_name),
+            source: wgpu::ShaderSource::Wgsl(composed.source.into()),
+        }));
+        self.modules
+            .insert(key.clone(), (module.clone(), composed.files));
+        Ok(module)
+    }
+
+    /// Preprocesses the file of the key without compiling it.
+    pub fn compose(&self, key: &ShaderKey) -> Result<ComposedShader, ShaderError> {
+        let mut composed = ComposedShader {
+            source: String::new(),
+            files: vec![],
+        };
+        let mut defines = key.defines.clone();
+        self.compose_file(&key.file_name, &mut defines, &mut composed)?;
+        Ok(composed)
+    }
+
+    fn compose_file(
+        &self,
+        file_name: &str,
+        defines: &mut BTreeSet<String>,
+        composed: &mut ComposedShader,
+    ) -> Result<(), ShaderError> {
+        composed.files.push(file_name.to_string());
+        let source = self.source(file_name)?;
+        let error = |line: usize, message: String| ShaderError {
+            file_name: file_name.to_string(),
+            line: Some(line + 1),
+            message,
+        };
+
+        let mut branches: Vec<Branch> = vec![];
+        for (line_index, line) in source.lines().enumerate() {
+            let active = branches.last().is_none_or(|branch| branch.active);
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    composed.source += line;
+                    composed.source.push('\n');
+                }
+                continue;
+            };
+
+            let (directive, argument) = directive
+                .trim()
+                .split_once(char::is_whitespace)
+                .map_or((directive.trim(), ""), |(directive, argument)| {
+                    (directive, argument.trim())
+                });
+            let name = || match argument {
+                "" => Err(error(line_index, format!("#{directive} needs a name"))),
+                name => Ok(name),
+            };
+            match directive {
+                "include" => {
+                    let Some(include) = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                    else {
+                        return Err(error(
+                            line_index,
+                            "#include needs a quoted file name".to_string(),
+                        ));
+                    };
+                    if active && !composed.files.iter().any(|file| file == include) {
+                        self.compose_file(include, defines, composed)?;
+                    }
+                }
+                "define" => {
+                    let name = name()?;
+                    if active {
+                        defines.insert(name.to_string());
+                    }
+                }
+                "ifdef" | "ifndef" => {
+                    let condition = defines.contains(name()?) == (directive == "ifdef");
+                    branches.push(Branch {
+                        line: line_index,
+                        parent_active: active,
+                        condition,
+                        active: active && condition,
+                        has_else: false,
+                    });
+                }
+                "else" => match branches.last_mut() {
+                    Some(branch) if !branch.has_else => {
+                        branch.has_else = true;
+                        branch.active = branch.parent_active && !branch.condition;
+                    }
+                    Some(_) => return Err(error(line_index, "duplicate #else".to_string())),
+                    None => return Err(error(line_index, "#else without #ifdef".to_string())),
+                },
+                "endif" => {
+                    if branches.pop().is_none() {
+                        return Err(error(line_index, "#endif without #ifdef".to_string()));
+                    }
+                }
+                _ => return Err(error(line_index, format!("unknown directive #{directive}"))),
+            }
+        }
+
+        match branches.last() {
+            Some(branch) => Err(error(branch.line, "#ifdef without #endif".to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn source(&self, file_name: &str) -> Result<Cow<'_, str>, ShaderError> {
+        if let Some(source) = self.sources.get(file_name) {
+            return Ok(Cow::Borrowed(source));
+        }
+        if let Some(dir) = &self.dir {
+            if let Ok(source) = std::fs::read_to_string(dir.join(file_name)) {
+                return Ok(Cow::Owned(source));
+            }
+        }
+        built_in_source(file_name)
+            .map(Cow::Borrowed)
+            .ok_or_else(|| ShaderError {
+                file_name: file_name.to_string(),
+                line: None,
+                message: "file not found".to_string(),
+            })
+    }
+}
+
+/// An `#ifdef` or `#ifndef` block that is being preprocessed.
+struct Branch {
+    line: usize,
+    parent_active: bool,
+    condition: bool,
+    active: bool,
+    has_else: bool,
+}
+
+/// The source of a shader as it was compiled into the crate.
+fn built_in_source(file_name: &str) -> Option<&'static str> {
+    Some(match file_name {
+        "camera_uniform.wgsl" => include_str!("camera_uniform.wgsl"),
+        "cull.wgsl" => include_str!("cull.wgsl"),
+        "gi.wgsl" => include_str!("gi.wgsl"),
+        "grid.wgsl" => include_str!("grid.wgsl"),
+        "hiz.wgsl" => include_str!("hiz.wgsl"),
+        "light.wgsl" => include_str!("light.wgsl"),
+        "light_uniform.wgsl" => include_str!("light_uniform.wgsl"),
+        "material.wgsl" => include_str!("material.wgsl"),
+        "raymarch.wgsl" => include_str!("raymarch.wgsl"),
+        "reflection.wgsl" => include_str!("reflection.wgsl"),
+        "reproject.wgsl" => include_str!("reproject.wgsl"),
+        "shader.wgsl" => include_str!("shader.wgsl"),
+        "voxel.wgsl" => include_str!("voxel.wgsl"),
+        "voxel_types.wgsl" => include_str!("voxel_types.wgsl"),
+        _ => return None,
+    })
+}
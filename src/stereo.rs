@@ -0,0 +1,219 @@
+use glam::Vec2;
+
+use crate::{
+    buffer,
+    camera::{Camera, StereoCamera},
+};
+
+/// How the images of the two eyes are provided.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Both eyes are rendered side by side into the frame, left eye on the left.
+    SideBySide,
+    /// Additionally copies the eyes into the layers of a two layer array texture, see `RenderState::stereo_texture()`.
+    /// This requires the frame to support being copied from, which is always the case when rendering headless.
+    LayeredArray,
+}
+
+/// The stereo camera rendered by the [`crate::RenderState`] and the cameras of its eyes.
+pub(crate) struct Stereo {
+    pub camera: StereoCamera,
+    pub eyes: [usize; 2],
+    layout: StereoLayout,
+    array_texture: Option<wgpu::Texture>,
+}
+
+impl Stereo {
+    pub fn new(
+        device: &wgpu::Device,
+        camera: StereoCamera,
+        eyes: [usize; 2],
+        layout: StereoLayout,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let array_texture = match layout {
+            StereoLayout::SideBySide => None,
+            StereoLayout::LayeredArray => Some(create_array_texture(device, config)),
+        };
+
+        Self {
+            camera,
+            eyes,
+            layout,
+            array_texture,
+        }
+    }
+
+    /// The cameras of both eyes for a frame of the given size with the lens shifts of their frusta.
+    pub fn eye_cameras(&self, config: &wgpu::SurfaceConfiguration) -> [(Camera, Vec2); 2] {
+        self.camera.eyes(config.width as f32, config.height as f32)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        if self.layout == StereoLayout::LayeredArray {
+            self.array_texture = Some(create_array_texture(device, config));
+        }
+    }
+
+    pub fn array_texture(&self) -> Option<&wgpu::Texture> {
+        self.array_texture.as_ref()
+    }
+
+    /// Reads the eyes of the last frame back to the CPU from the layers of the array texture,
+    /// or from the halves of the side-by-side `frame` if there is none.
+    pub fn read_eye_images(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame: &wgpu::Texture,
+    ) -> anyhow::Result<[image::RgbaImage; 2]> {
+        let (texture, width, origins) = match &self.array_texture {
+            Some(array_texture) => (
+                array_texture,
+                array_texture.width(),
+                [0, 1].map(|layer| wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                }),
+            ),
+            None => {
+                let eye_width = frame.width() / 2;
+                (
+                    frame,
+                    eye_width,
+                    [0, eye_width].map(|x| wgpu::Origin3d { x, y: 0, z: 0 }),
+                )
+            }
+        };
+        let [left, right] = origins
+            .map(|origin| read_image(device, queue, texture, origin, width, texture.height()));
+        Ok([left?, right?])
+    }
+
+    /// Copies the halves of the rendered frame into the layers of the array texture.
+    pub fn copy_eyes(&self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::Texture) {
+        let Some(array_texture) = &self.array_texture else {
+            return;
+        };
+        if !frame.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return;
+        }
+
+        let size = array_texture.size();
+        for layer in 0..2 {
+            encoder.copy_texture_to_texture(
+                wgpu::ImageCopyTexture {
+                    texture: frame,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: layer * size.width,
+                        y: 0,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyTexture {
+                    texture: array_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+    }
+}
+
+fn create_array_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("stereo_array_texture"),
+        size: wgpu::Extent3d {
+            width: (config.width / 2).max(1),
+            height: config.height,
+            depth_or_array_layers: 2,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Reads a `width` x `height` region of a layer of the texture back to the CPU, this waits until the GPU finished rendering.
+fn read_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    origin: wgpu::Origin3d,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<image::RgbaImage> {
+    let bytes_per_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Eye Readback Buffer"),
+        size: (bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Eye Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+    let data = buffer::map_read(device, &buffer)?;
+
+    let bgra = matches!(
+        texture.format(),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    );
+    Ok(image::RgbaImage::from_fn(width, height, |x, y| {
+        let start = (y * bytes_per_row + x * 4) as usize;
+        let [r, g, b, a] = [
+            data[start],
+            data[start + 1],
+            data[start + 2],
+            data[start + 3],
+        ];
+        if bgra {
+            image::Rgba([b, g, r, a])
+        } else {
+            image::Rgba([r, g, b, a])
+        }
+    }))
+}
@@ -1,9 +1,137 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::bail;
+
 use image::GenericImageView;
 
+/// How textures are filtered when they are sampled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TextureFiltering {
+    /// The nearest texel of the nearest mip level.
+    Nearest,
+    /// Blends the closest texels of the nearest mip level.
+    Bilinear,
+    /// Blends the closest texels of the two nearest mip levels.
+    #[default]
+    Trilinear,
+    /// Trilinear filtering with up to the given number of samples along the direction of anisotropy, clamped between 1 and 16.
+    /// Keeps surfaces at grazing angles sharp.
+    Anisotropic(u16),
+}
+
+/// The textures of a material, see `RenderState::load_model_with_sampler()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
+}
+
+/// Describes how a texture is sampled. Equal settings share a single [`wgpu::Sampler`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerSettings {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub filtering: TextureFiltering,
+    pub lod_min_clamp: f32,
+    pub lod_max_clamp: f32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            filtering: TextureFiltering::default(),
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 32.0,
+        }
+    }
+}
+
+impl SamplerSettings {
+    /// Repeats the texture in every direction, for tiling textures.
+    pub fn repeat(filtering: TextureFiltering) -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            filtering,
+            ..Default::default()
+        }
+    }
+
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let (filter, mipmap_filter, anisotropy_clamp) = match self.filtering {
+            TextureFiltering::Nearest => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, 1),
+            TextureFiltering::Bilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, 1),
+            TextureFiltering::Trilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, 1),
+            TextureFiltering::Anisotropic(samples) => (
+                wgpu::FilterMode::Linear,
+                wgpu::FilterMode::Linear,
+                samples.clamp(1, 16),
+            ),
+        };
+
+        wgpu::SamplerDescriptor {
+            label: Some("texture_sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            lod_min_clamp: self.lod_min_clamp,
+            lod_max_clamp: self.lod_max_clamp,
+            anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+}
+
+/// Hands out one [`wgpu::Sampler`] per distinct [`SamplerSettings`].
+#[derive(Default)]
+pub(crate) struct SamplerCache {
+    samplers: HashMap<SamplerKey, Arc<wgpu::Sampler>>,
+}
+
+/// [`SamplerSettings`] with the floats as bits, so they can be hashed
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    address_modes: [wgpu::AddressMode; 3],
+    filtering: TextureFiltering,
+    lod_clamp: [u32; 2],
+}
+
+impl SamplerCache {
+    pub fn get(&mut self, device: &wgpu::Device, settings: &SamplerSettings) -> Arc<wgpu::Sampler> {
+        let key = SamplerKey {
+            address_modes: [
+                settings.address_mode_u,
+                settings.address_mode_v,
+                settings.address_mode_w,
+            ],
+            filtering: settings.filtering,
+            lod_clamp: [
+                settings.lod_min_clamp.to_bits(),
+                settings.lod_max_clamp.to_bits(),
+            ],
+        };
+        self.samplers
+            .entry(key)
+            .or_insert_with(|| Arc::new(device.create_sampler(&settings.descriptor())))
+            .clone()
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    /// `D2` for single textures, `Cube` for cubemaps and `D2Array` for texture arrays
+    pub view_dimension: wgpu::TextureViewDimension,
+    /// Shared with every other texture using the same [`SamplerSettings`]
+    pub sampler: Arc<wgpu::Sampler>,
 }
 
 impl Texture {
@@ -31,7 +159,7 @@ impl Texture {
         };
         let texture = device.create_texture(&desc);
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
@@ -42,11 +170,12 @@ impl Texture {
             lod_min_clamp: 0.0,
             lod_max_clamp: 100.0,
             ..Default::default()
-        });
+        }));
 
         Self {
             texture,
             view,
+            view_dimension: wgpu::TextureViewDimension::D2,
             sampler,
         }
     }
@@ -57,79 +186,976 @@ impl Texture {
         bytes: &[u8],
         label: &str,
         is_normal_map: bool,
+        sampler: Arc<wgpu::Sampler>,
     ) -> Result<Self, image::ImageError> {
-        let img = image::load_from_memory(bytes)?;
+        let img = load_image(bytes)?;
         Ok(Self::from_image(
             device,
             queue,
             &img,
             Some(label),
             is_normal_map,
+            sampler,
         ))
     }
 
+    /// Floating point images, like `.hdr` and `.exr` files, keep their range in a [`wgpu::TextureFormat::Rgba16Float`] texture.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
         is_normal_map: bool,
+        sampler: Arc<wgpu::Sampler>,
     ) -> Self {
-        let dimensions = img.dimensions();
-        let rgba = img.to_rgba8();
+        let (format, levels) = image_levels(img, is_normal_map);
+        let texture = upload_layers(device, queue, &[&levels], img.dimensions(), format, label);
+        Self::with_view(texture, wgpu::TextureViewDimension::D2, sampler)
+    }
+
+    /// Creates a linear floating point texture, for HDR environment maps, emissive textures or data like heightmaps.
+    /// `format` has to be [`wgpu::TextureFormat::Rgba16Float`] or [`wgpu::TextureFormat::Rgba32Float`].
+    /// `Rgba32Float` textures can't be filtered, so they only work with non-filtering samplers or `textureLoad()`
+    /// and [`Material`](crate::model::Material)s refuse them.
+    pub fn from_hdr_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::Rgba32FImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let levels = hdr_levels(img, format)?;
+        let texture = upload_layers(device, queue, &[&levels], img.dimensions(), format, label);
+        Ok(Self::with_view(
+            texture,
+            wgpu::TextureViewDimension::D2,
+            sampler,
+        ))
+    }
+
+    /// Creates a cubemap from its faces in the order +X, -X, +Y, -Y, +Z, -Z.
+    /// The faces have to be square and of the same size, floating point faces create an HDR cubemap.
+    pub fn cubemap_from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: [&image::DynamicImage; 6],
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let dimensions = faces[0].dimensions();
+        if dimensions.0 != dimensions.1 {
+            bail!("cubemap faces have to be square, not {dimensions:?}");
+        }
+        let texture = Self::array_from_images(device, queue, &faces, label, false, sampler)?;
+        Ok(Self::with_view(
+            texture.texture,
+            wgpu::TextureViewDimension::Cube,
+            texture.sampler,
+        ))
+    }
+
+    /// Creates a cubemap with faces of `face_size` by projecting an equirectangular panorama onto them,
+    /// the center of the image ends up in the -Z direction.
+    pub fn cubemap_from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        face_size: u32,
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        if face_size == 0 {
+            bail!("cubemap faces can't be empty");
+        }
+        if img.width() == 0 || img.height() == 0 {
+            bail!("the panorama can't be empty");
+        }
+        let faces = equirectangular_to_cube_faces(&img.to_rgba32f(), face_size).map(|face| {
+            if is_hdr(img) {
+                image::DynamicImage::ImageRgba32F(face)
+            } else {
+                image::DynamicImage::ImageRgba32F(face).to_rgba8().into()
+            }
+        });
+        Self::cubemap_from_faces(device, queue, faces.each_ref(), label, sampler)
+    }
+
+    /// Creates a texture array with one layer per image, for example to splat terrain materials.
+    /// All images need the same size, the format of the first image decides whether the array is HDR.
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[&image::DynamicImage],
+        label: Option<&str>,
+        is_normal_map: bool,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let Some(first) = images.first() else {
+            bail!("texture arrays need at least one image");
+        };
+        let dimensions = first.dimensions();
+        if dimensions.0 == 0 || dimensions.1 == 0 {
+            bail!("texture array layers can't be empty");
+        }
+        if let Some(image) = images.iter().find(|image| image.dimensions() != dimensions) {
+            bail!(
+                "all layers need a size of {dimensions:?}, not {:?}",
+                image.dimensions()
+            );
+        }
+
+        let hdr = is_hdr(first);
+        let format = image_levels(first, is_normal_map).0;
+        let layers = images
+            .iter()
+            .map(|image| {
+                if hdr {
+                    hdr_levels(&image.to_rgba32f(), format)
+                } else {
+                    Ok(image_levels(&image.to_rgba8().into(), is_normal_map).1)
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let layers = layers.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        let texture = upload_layers(device, queue, &layers, dimensions, format, label);
+        Ok(Self::with_view(
+            texture,
+            wgpu::TextureViewDimension::D2Array,
+            sampler,
+        ))
+    }
+
+    /// Loads a KTX2 or DDS container including its pre-baked mip levels, only the first layer is used.
+    /// Block compressed formats the device doesn't have the `TEXTURE_COMPRESSION_*` feature for are decompressed on the CPU,
+    /// which also generates the mip chain if the file doesn't contain one. Signed and HDR formats can't be decompressed and fail to load then.
+    /// Compressed textures can't be rendered to, so a file without mip levels that the device samples directly stays without them,
+    /// bake them into the file instead.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        is_normal_map: bool,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> anyhow::Result<Self> {
+        let image = compressed::CompressedImage::parse(bytes, is_normal_map)?;
+        let dimensions = (image.width, image.height);
+        let (block_width, block_height) = image.format.block_dimensions();
+        let supported = device.features().contains(image.format.required_features())
+            && dimensions.0 % block_width == 0
+            && dimensions.1 % block_height == 0;
+
+        if supported {
+            let texture = upload_layers(
+                device,
+                queue,
+                &[&image.levels],
+                dimensions,
+                image.format,
+                Some(label),
+            );
+            return Ok(Self::with_view(
+                texture,
+                wgpu::TextureViewDimension::D2,
+                sampler,
+            ));
+        }
+
+        let format = compressed::decompressed_format(image.format)?;
+        let mut levels = image.decode()?;
+        if levels.len() == 1 {
+            levels = generate_mip_chain(levels.pop().unwrap(), is_normal_map);
+        }
+        let texture = upload_layers(device, queue, &[&levels], dimensions, format, Some(label));
+        Ok(Self::with_view(
+            texture,
+            wgpu::TextureViewDimension::D2,
+            sampler,
+        ))
+    }
 
+    /// The layout entries for the view of a texture with the given dimension at `binding` and its sampler right after it.
+    pub fn bind_group_layout_entries(
+        binding: u32,
+        view_dimension: wgpu::TextureViewDimension,
+        visibility: wgpu::ShaderStages,
+    ) -> [wgpu::BindGroupLayoutEntry; 2] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    /// The entries matching [`Texture::bind_group_layout_entries()`].
+    pub fn bind_group_entries(&self, binding: u32) -> [wgpu::BindGroupEntry<'_>; 2] {
+        [
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Copies the first mip level of the first layer back to the CPU and decodes it to 8-bit RGBA,
+    /// this waits until the GPU finished all submitted work.
+    pub(crate) fn read_rgba(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let format = self.texture.format();
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
             depth_or_array_layers: 1,
+            ..self.texture.size()
         };
-        let format = if is_normal_map {
-            wgpu::TextureFormat::Rgba8Unorm
-        } else {
-            wgpu::TextureFormat::Rgba8UnormSrgb
+        let (block_width, block_height) = format.block_dimensions();
+        let Some(block_size) = format.block_size(None) else {
+            bail!("{format:?} can't be read back");
         };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
-            size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        let bytes_per_row = size.width.div_ceil(block_width) * block_size;
+        let padded_bytes_per_row =
+            wgpu::util::align_to(bytes_per_row, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let rows = size.height.div_ceil(block_height);
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture Readback Buffer"),
+            size: (padded_bytes_per_row * rows) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(rows),
+                },
             },
-            size,
+            size.physical_size(format),
         );
+        queue.submit(std::iter::once(encoder.finish()));
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        let padded = crate::buffer::map_read(device, &buffer)?;
+        let data = padded
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..bytes_per_row as usize])
+            .copied()
+            .collect::<Vec<_>>();
+        match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => {
+                Ok(image::RgbaImage::from_raw(size.width, size.height, data).unwrap())
+            }
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => {
+                let mut image = image::RgbaImage::from_raw(size.width, size.height, data).unwrap();
+                image.pixels_mut().for_each(|pixel| pixel.0.swap(0, 2));
+                Ok(image)
+            }
+            format if format.is_compressed() => {
+                compressed::decode_level(format, &data, size.width, size.height)
+            }
+            _ => bail!("{format:?} can't be read back"),
+        }
+    }
+
+    fn with_view(
+        texture: wgpu::Texture,
+        view_dimension: wgpu::TextureViewDimension,
+        sampler: Arc<wgpu::Sampler>,
+    ) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(view_dimension),
             ..Default::default()
         });
 
         Self {
             texture,
             view,
+            view_dimension,
             sampler,
         }
     }
 }
+
+/// Decodes an image, unlike [`image::load_from_memory()`] this keeps Radiance `.hdr` files in floating point.
+pub fn load_image(bytes: &[u8]) -> Result<image::DynamicImage, image::ImageError> {
+    if image::guess_format(bytes)? != image::ImageFormat::Hdr {
+        return image::load_from_memory(bytes);
+    }
+
+    let decoder = image::codecs::hdr::HdrDecoder::new(bytes)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let img = image::Rgba32FImage::from_fn(metadata.width, metadata.height, |x, y| {
+        let [r, g, b] = pixels[(y * metadata.width + x) as usize].0;
+        image::Rgba([r, g, b, 1.0])
+    });
+    Ok(img.into())
+}
+
+fn is_hdr(img: &image::DynamicImage) -> bool {
+    matches!(
+        img,
+        image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+    )
+}
+
+/// The format and the mip levels of an image, floating point images become [`wgpu::TextureFormat::Rgba16Float`].
+fn image_levels(
+    img: &image::DynamicImage,
+    is_normal_map: bool,
+) -> (wgpu::TextureFormat, Vec<Vec<u8>>) {
+    if is_hdr(img) {
+        let format = wgpu::TextureFormat::Rgba16Float;
+        let levels = hdr_levels(&img.to_rgba32f(), format).expect("Rgba16Float is supported");
+        return (format, levels);
+    }
+
+    let format = if is_normal_map {
+        wgpu::TextureFormat::Rgba8Unorm
+    } else {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    };
+    let levels = generate_mip_chain(img.to_rgba8(), is_normal_map)
+        .into_iter()
+        .map(image::RgbaImage::into_raw)
+        .collect();
+    (format, levels)
+}
+
+/// The mip levels of a floating point image, averaged without any conversion.
+fn hdr_levels(
+    img: &image::Rgba32FImage,
+    format: wgpu::TextureFormat,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    if !matches!(
+        format,
+        wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+    ) {
+        bail!("HDR textures can't have the format {format:?}, only Rgba16Float or Rgba32Float");
+    }
+
+    Ok(mip_chain(img.clone(), |texels| {
+        image::Rgba(std::array::from_fn(|channel| {
+            texels.iter().map(|texel| texel[channel]).sum::<f32>() / 4.0
+        }))
+    })
+    .iter()
+    .map(|level| match format {
+        wgpu::TextureFormat::Rgba32Float => bytemuck::cast_slice(level.as_raw()).to_vec(),
+        _ => level
+            .as_raw()
+            .iter()
+            .flat_map(|c| half::f16::from_f32(*c).to_le_bytes())
+            .collect(),
+    })
+    .collect())
+}
+
+/// Creates a texture from the tightly packed data of each mip level of each layer, starting with the full size.
+fn upload_layers(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layers: &[&[impl std::ops::Deref<Target = [u8]>]],
+    dimensions: (u32, u32),
+    format: wgpu::TextureFormat,
+    label: Option<&str>,
+) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width: dimensions.0,
+        height: dimensions.1,
+        depth_or_array_layers: layers.len() as u32,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label,
+        size,
+        mip_level_count: layers[0].len() as u32,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_size(None).unwrap();
+    for (layer, levels) in layers.iter().enumerate() {
+        for (mip_level, level) in levels.iter().enumerate() {
+            let level_size = wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size.mip_level_size(mip_level as u32, wgpu::TextureDimension::D2)
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                level,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(level_size.width.div_ceil(block_width) * block_size),
+                    rows_per_image: Some(level_size.height.div_ceil(block_height)),
+                },
+                level_size.physical_size(format),
+            );
+        }
+    }
+
+    texture
+}
+
+/// Projects an equirectangular panorama onto the six faces of a cube, in the order +X, -X, +Y, -Y, +Z, -Z.
+fn equirectangular_to_cube_faces(
+    img: &image::Rgba32FImage,
+    face_size: u32,
+) -> [image::Rgba32FImage; 6] {
+    use std::f32::consts::PI;
+
+    let (width, height) = img.dimensions();
+    // Bilinear filtering that wraps around horizontally
+    let sample = |u: f32, v: f32| {
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let texel = |x: f32, y: f32| {
+            img.get_pixel(
+                (x as i64).rem_euclid(width as i64) as u32,
+                (y as u32).min(height - 1),
+            )
+            .0
+        };
+        let [a, b, c, d] = [
+            texel(x0, y0),
+            texel(x0 + 1.0, y0),
+            texel(x0, y0 + 1.0),
+            texel(x0 + 1.0, y0 + 1.0),
+        ];
+        image::Rgba(std::array::from_fn(|channel| {
+            let top = a[channel] + (b[channel] - a[channel]) * tx;
+            let bottom = c[channel] + (d[channel] - c[channel]) * tx;
+            top + (bottom - top) * ty
+        }))
+    };
+
+    std::array::from_fn(|face| {
+        image::Rgba32FImage::from_fn(face_size, face_size, |x, y| {
+            let u = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+            let direction = match face {
+                0 => glam::Vec3::new(1.0, -v, -u),
+                1 => glam::Vec3::new(-1.0, -v, u),
+                2 => glam::Vec3::new(u, 1.0, v),
+                3 => glam::Vec3::new(u, -1.0, -v),
+                4 => glam::Vec3::new(u, -v, 1.0),
+                _ => glam::Vec3::new(-u, -v, -1.0),
+            }
+            .normalize();
+            sample(
+                0.5 + direction.x.atan2(-direction.z) / (2.0 * PI),
+                direction.y.clamp(-1.0, 1.0).acos() / PI,
+            )
+        })
+    })
+}
+
+/// Decodes an 8 bit sRGB channel to linear.
+pub(crate) fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear channel to sRGB, without quantizing it.
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Downsamples the image until it is a single texel, the first level is the image itself.
+/// Color textures are averaged in linear space, normal maps are renormalized after averaging.
+fn generate_mip_chain(image: image::RgbaImage, is_normal_map: bool) -> Vec<image::RgbaImage> {
+    // Decoding every texel is the hot path, so the 256 possible values are decoded once
+    let srgb_to_linear: [f32; 256] = std::array::from_fn(|c| srgb_to_linear(c as u8));
+    let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+    mip_chain(image, |texels| {
+        let average = |decode: &dyn Fn(u8) -> f32, channel: usize| {
+            texels
+                .iter()
+                .map(|texel| decode(texel[channel]))
+                .sum::<f32>()
+                / 4.0
+        };
+
+        if is_normal_map {
+            let normal = glam::Vec3::from_array(
+                [0, 1, 2].map(|channel| average(&|c| c as f32 / 255.0 * 2.0 - 1.0, channel)),
+            )
+            .normalize_or_zero();
+            let [r, g, b] = (normal * 0.5 + 0.5).to_array().map(to_byte);
+            image::Rgba([r, g, b, to_byte(average(&|c| c as f32 / 255.0, 3))])
+        } else {
+            let [r, g, b] = [0, 1, 2].map(|channel| {
+                to_byte(linear_to_srgb(average(
+                    &|c| srgb_to_linear[c as usize],
+                    channel,
+                )))
+            });
+            image::Rgba([r, g, b, to_byte(average(&|c| c as f32 / 255.0, 3))])
+        }
+    })
+}
+
+/// Halves the image until it is a single texel, `average` combines the four texels of the previous level.
+fn mip_chain<P: image::Pixel>(
+    image: image::ImageBuffer<P, Vec<P::Subpixel>>,
+    average: impl Fn([P; 4]) -> P,
+) -> Vec<image::ImageBuffer<P, Vec<P::Subpixel>>> {
+    let mut chain = vec![image];
+    loop {
+        let previous = chain.last().unwrap();
+        let (width, height) = previous.dimensions();
+        if width == 1 && height == 1 {
+            break;
+        }
+
+        let level = image::ImageBuffer::from_fn((width / 2).max(1), (height / 2).max(1), |x, y| {
+            // Odd sizes repeat the last row or column
+            average([(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                *previous.get_pixel((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1))
+            }))
+        });
+        chain.push(level);
+    }
+
+    chain
+}
+
+/// Reading KTX2 and DDS containers and decompressing their contents.
+mod compressed {
+    use std::io::Read;
+
+    use anyhow::{anyhow, bail};
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+    const KTX2_MAGIC: [u8; 12] = [
+        0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+    ];
+
+    /// The first layer of a texture with all mip levels as they are stored in the file.
+    pub struct CompressedImage {
+        pub format: TextureFormat,
+        pub width: u32,
+        pub height: u32,
+        pub levels: Vec<Vec<u8>>,
+    }
+
+    impl CompressedImage {
+        /// Legacy DDS files don't tell whether they are sRGB, so color textures are assumed to be.
+        /// Normal maps are never sRGB, whatever their file says.
+        pub fn parse(bytes: &[u8], is_normal_map: bool) -> anyhow::Result<Self> {
+            let mut image = if bytes.starts_with(&KTX2_MAGIC) {
+                parse_ktx2(bytes)?
+            } else if bytes.starts_with(b"DDS ") {
+                parse_dds(bytes, is_normal_map)?
+            } else {
+                bail!("not a KTX2 or DDS file")
+            };
+            if is_normal_map {
+                image.format = image.format.remove_srgb_suffix();
+            }
+            Ok(image)
+        }
+
+        /// Decompresses every level to 8 bit RGBA.
+        pub fn decode(&self) -> anyhow::Result<Vec<image::RgbaImage>> {
+            self.levels
+                .iter()
+                .enumerate()
+                .map(|(level, data)| {
+                    decode_level(
+                        self.format,
+                        data,
+                        (self.width >> level).max(1),
+                        (self.height >> level).max(1),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    /// The format a texture decompressed on the CPU is uploaded as. Signed and HDR formats are refused,
+    /// decompressing them to 8 bit unsigned colors would change their values.
+    pub fn decompressed_format(format: TextureFormat) -> anyhow::Result<TextureFormat> {
+        match format {
+            TextureFormat::Bc4RSnorm
+            | TextureFormat::Bc5RgSnorm
+            | TextureFormat::Bc6hRgbUfloat
+            | TextureFormat::Bc6hRgbFloat
+            | TextureFormat::EacR11Snorm
+            | TextureFormat::EacRg11Snorm => bail!(
+                "{format:?} can't be decompressed without changing its values, sampling it needs the {:?} feature \
+                 and a size that is a multiple of the block size",
+                format.required_features()
+            ),
+            _ if format.is_srgb() => Ok(TextureFormat::Rgba8UnormSrgb),
+            _ => Ok(TextureFormat::Rgba8Unorm),
+        }
+    }
+
+    /// The number of bytes a level of the given size takes up.
+    fn level_length(format: TextureFormat, width: u32, height: u32) -> usize {
+        let (block_width, block_height) = format.block_dimensions();
+        (width.div_ceil(block_width) * height.div_ceil(block_height)) as usize
+            * format.block_size(None).unwrap() as usize
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> anyhow::Result<CompressedImage> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("invalid KTX2 file: {e:?}"))?;
+        let header = reader.header();
+        if header.pixel_depth > 1 {
+            bail!("3D textures are not supported");
+        }
+        let format = header
+            .format
+            .and_then(ktx2_format)
+            .ok_or_else(|| anyhow!("unsupported KTX2 format {:?}", header.format))?;
+
+        let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+        let mut levels = vec![];
+        for (level, data) in reader.levels().enumerate() {
+            let data = match header.supercompression_scheme {
+                None => data.data.to_vec(),
+                Some(ktx2::SupercompressionScheme::Zstandard) => {
+                    let mut decoded = vec![];
+                    ruzstd::decoding::StreamingDecoder::new(data.data)
+                        .map_err(|e| anyhow!("invalid zstd data: {e:?}"))?
+                        .read_to_end(&mut decoded)?;
+                    decoded
+                }
+                Some(ktx2::SupercompressionScheme::ZLIB) => {
+                    let mut decoded = vec![];
+                    flate2::read::ZlibDecoder::new(data.data).read_to_end(&mut decoded)?;
+                    decoded
+                }
+                Some(scheme) => bail!("unsupported KTX2 supercompression {scheme:?}"),
+            };
+            // Layers and faces of a level are stored one after another
+            let length = level_length(format, (width >> level).max(1), (height >> level).max(1));
+            if data.len() < length {
+                bail!("KTX2 level {level} is truncated");
+            }
+            levels.push(data[..length].to_vec());
+        }
+
+        Ok(CompressedImage {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    fn ktx2_format(format: ktx2::Format) -> Option<TextureFormat> {
+        use ktx2::Format as K;
+
+        let astc_blocks = [
+            (
+                K::ASTC_4x4_UNORM_BLOCK,
+                K::ASTC_4x4_SRGB_BLOCK,
+                AstcBlock::B4x4,
+            ),
+            (
+                K::ASTC_5x4_UNORM_BLOCK,
+                K::ASTC_5x4_SRGB_BLOCK,
+                AstcBlock::B5x4,
+            ),
+            (
+                K::ASTC_5x5_UNORM_BLOCK,
+                K::ASTC_5x5_SRGB_BLOCK,
+                AstcBlock::B5x5,
+            ),
+            (
+                K::ASTC_6x5_UNORM_BLOCK,
+                K::ASTC_6x5_SRGB_BLOCK,
+                AstcBlock::B6x5,
+            ),
+            (
+                K::ASTC_6x6_UNORM_BLOCK,
+                K::ASTC_6x6_SRGB_BLOCK,
+                AstcBlock::B6x6,
+            ),
+            (
+                K::ASTC_8x5_UNORM_BLOCK,
+                K::ASTC_8x5_SRGB_BLOCK,
+                AstcBlock::B8x5,
+            ),
+            (
+                K::ASTC_8x6_UNORM_BLOCK,
+                K::ASTC_8x6_SRGB_BLOCK,
+                AstcBlock::B8x6,
+            ),
+            (
+                K::ASTC_8x8_UNORM_BLOCK,
+                K::ASTC_8x8_SRGB_BLOCK,
+                AstcBlock::B8x8,
+            ),
+            (
+                K::ASTC_10x5_UNORM_BLOCK,
+                K::ASTC_10x5_SRGB_BLOCK,
+                AstcBlock::B10x5,
+            ),
+            (
+                K::ASTC_10x6_UNORM_BLOCK,
+                K::ASTC_10x6_SRGB_BLOCK,
+                AstcBlock::B10x6,
+            ),
+            (
+                K::ASTC_10x8_UNORM_BLOCK,
+                K::ASTC_10x8_SRGB_BLOCK,
+                AstcBlock::B10x8,
+            ),
+            (
+                K::ASTC_10x10_UNORM_BLOCK,
+                K::ASTC_10x10_SRGB_BLOCK,
+                AstcBlock::B10x10,
+            ),
+            (
+                K::ASTC_12x10_UNORM_BLOCK,
+                K::ASTC_12x10_SRGB_BLOCK,
+                AstcBlock::B12x10,
+            ),
+            (
+                K::ASTC_12x12_UNORM_BLOCK,
+                K::ASTC_12x12_SRGB_BLOCK,
+                AstcBlock::B12x12,
+            ),
+        ];
+        for (unorm, srgb, block) in astc_blocks {
+            if format == unorm || format == srgb {
+                let channel = if format == srgb {
+                    AstcChannel::UnormSrgb
+                } else {
+                    AstcChannel::Unorm
+                };
+                return Some(TextureFormat::Astc { block, channel });
+            }
+        }
+
+        Some(match format {
+            K::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+            K::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+            K::B8G8R8A8_UNORM => TextureFormat::Bgra8Unorm,
+            K::B8G8R8A8_SRGB => TextureFormat::Bgra8UnormSrgb,
+            K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+            K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+            K::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+            K::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+            K::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+            K::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+            K::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+            K::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+            K::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+            K::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+            K::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+            K::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+            K::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+            K::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+            K::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+            K::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+            K::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+            K::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+            K::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+            K::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+            K::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+            K::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+            K::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+            K::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+            _ => return None,
+        })
+    }
+
+    fn parse_dds(bytes: &[u8], is_normal_map: bool) -> anyhow::Result<CompressedImage> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        if dds.get_depth() > 1 {
+            bail!("3D textures are not supported");
+        }
+        let format = match dds.header10 {
+            Some(_) => dds.get_dxgi_format().and_then(dxgi_format),
+            None => dds
+                .get_dxgi_format()
+                .and_then(dxgi_format)
+                .or_else(|| dds.get_d3d_format().and_then(d3d_format))
+                .map(|format| {
+                    if is_normal_map {
+                        format.remove_srgb_suffix()
+                    } else {
+                        format.add_srgb_suffix()
+                    }
+                }),
+        }
+        .ok_or_else(|| anyhow!("unsupported DDS format"))?;
+
+        let (width, height) = (dds.get_width(), dds.get_height());
+        // The first layer comes first and contains every level
+        let mut data = &dds.data[..];
+        let mut levels = vec![];
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let length = level_length(format, (width >> level).max(1), (height >> level).max(1));
+            if data.len() < length {
+                bail!("DDS level {level} is truncated");
+            }
+            let (level_data, rest) = data.split_at(length);
+            levels.push(level_data.to_vec());
+            data = rest;
+        }
+
+        Ok(CompressedImage {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<TextureFormat> {
+        use ddsfile::DxgiFormat as D;
+
+        Some(match format {
+            D::R8G8B8A8_UNorm => TextureFormat::Rgba8Unorm,
+            D::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
+            D::B8G8R8A8_UNorm => TextureFormat::Bgra8Unorm,
+            D::B8G8R8A8_UNorm_sRGB => TextureFormat::Bgra8UnormSrgb,
+            D::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+            D::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+            D::BC2_UNorm => TextureFormat::Bc2RgbaUnorm,
+            D::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+            D::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+            D::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+            D::BC4_UNorm => TextureFormat::Bc4RUnorm,
+            D::BC4_SNorm => TextureFormat::Bc4RSnorm,
+            D::BC5_UNorm => TextureFormat::Bc5RgUnorm,
+            D::BC5_SNorm => TextureFormat::Bc5RgSnorm,
+            D::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
+            D::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
+            D::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+            D::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+            _ => return None,
+        })
+    }
+
+    fn d3d_format(format: ddsfile::D3DFormat) -> Option<TextureFormat> {
+        use ddsfile::D3DFormat as D;
+
+        Some(match format {
+            D::A8B8G8R8 => TextureFormat::Rgba8Unorm,
+            D::A8R8G8B8 => TextureFormat::Bgra8Unorm,
+            _ => return None,
+        })
+    }
+
+    pub fn decode_level(
+        format: TextureFormat,
+        data: &[u8],
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<image::RgbaImage> {
+        use texture2ddecoder as decoder;
+
+        let (w, h) = (width as usize, height as usize);
+        let mut pixels = vec![0_u32; w * h];
+        let result = match format {
+            TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => {
+                decoder::decode_bc1a(data, w, h, &mut pixels)
+            }
+            TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => {
+                decoder::decode_bc2(data, w, h, &mut pixels)
+            }
+            TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => {
+                decoder::decode_bc3(data, w, h, &mut pixels)
+            }
+            TextureFormat::Bc4RUnorm => decoder::decode_bc4(data, w, h, &mut pixels),
+            TextureFormat::Bc5RgUnorm => decoder::decode_bc5(data, w, h, &mut pixels),
+            TextureFormat::Bc6hRgbUfloat => decoder::decode_bc6_unsigned(data, w, h, &mut pixels),
+            TextureFormat::Bc6hRgbFloat => decoder::decode_bc6_signed(data, w, h, &mut pixels),
+            TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => {
+                decoder::decode_bc7(data, w, h, &mut pixels)
+            }
+            TextureFormat::Etc2Rgb8Unorm | TextureFormat::Etc2Rgb8UnormSrgb => {
+                decoder::decode_etc2_rgb(data, w, h, &mut pixels)
+            }
+            TextureFormat::Etc2Rgb8A1Unorm | TextureFormat::Etc2Rgb8A1UnormSrgb => {
+                decoder::decode_etc2_rgba1(data, w, h, &mut pixels)
+            }
+            TextureFormat::Etc2Rgba8Unorm | TextureFormat::Etc2Rgba8UnormSrgb => {
+                decoder::decode_etc2_rgba8(data, w, h, &mut pixels)
+            }
+            TextureFormat::EacR11Unorm => decoder::decode_eacr(data, w, h, &mut pixels),
+            TextureFormat::EacR11Snorm => decoder::decode_eacr_signed(data, w, h, &mut pixels),
+            TextureFormat::EacRg11Unorm => decoder::decode_eacrg(data, w, h, &mut pixels),
+            TextureFormat::EacRg11Snorm => decoder::decode_eacrg_signed(data, w, h, &mut pixels),
+            TextureFormat::Astc {
+                block: _,
+                channel: AstcChannel::Unorm | AstcChannel::UnormSrgb,
+            } => {
+                let (block_width, block_height) = format.block_dimensions();
+                decoder::decode_astc(
+                    data,
+                    w,
+                    h,
+                    block_width as usize,
+                    block_height as usize,
+                    &mut pixels,
+                )
+            }
+            _ => bail!("{format:?} can't be decompressed"),
+        };
+        result.map_err(|e| anyhow!("failed to decompress {format:?}: {e}"))?;
+
+        // Formats without alpha are opaque
+        let opaque = format.components() < 4;
+        Ok(image::RgbaImage::from_fn(width, height, |x, y| {
+            let [b, g, r, a] = pixels[(y * width + x) as usize].to_le_bytes();
+            image::Rgba([r, g, b, if opaque { 255 } else { a }])
+        }))
+    }
+}
@@ -0,0 +1,1213 @@
+use std::{collections::HashMap, num::NonZeroU64, ops::Range};
+
+use glam::{IVec3, UVec3, Vec3};
+
+use crate::{buffer::GrowableBuffer, culling::Aabb, reflection::ReflectionSettings};
+
+/// A single voxel. Voxels are represented as points with an individual radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelPoint {
+    pub position: Vec3,
+    pub radius: f32,
+    /// Index into the [`VoxelMaterial`]s of the [`VoxelVolume`]
+    pub material: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelMaterial {
+    pub color: Vec3,
+    pub roughness: f32,
+    pub emission: f32,
+}
+
+impl Default for VoxelMaterial {
+    fn default() -> Self {
+        Self {
+            color: Vec3::ONE,
+            roughness: 1.0,
+            emission: 0.0,
+        }
+    }
+}
+
+/// How the distances to neighbouring voxels are blended together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmoothMin {
+    /// Only blends voxels closer than the blend radius, which makes it the cheapest option.
+    #[default]
+    Polynomial,
+    /// Blends all voxels, resulting in softer shapes that grow with the number of voxels.
+    Exponential,
+}
+
+/// A set of [`VoxelPoint`]s together with the [`VoxelMaterial`]s they use.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelVolume {
+    pub points: Vec<VoxelPoint>,
+    pub materials: Vec<VoxelMaterial>,
+}
+
+impl VoxelVolume {
+    pub fn new(points: Vec<VoxelPoint>, materials: Vec<VoxelMaterial>) -> Self {
+        Self { points, materials }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct VoxelPointRaw {
+    position: [f32; 3],
+    radius: f32,
+    material: u32,
+    // Storage buffers align this struct to 16 bytes
+    _padding: [u32; 3],
+}
+
+impl VoxelPointRaw {
+    /// Points index the materials of their volume, which start at `material_offset` in the material buffer.
+    fn new(point: &VoxelPoint, material_offset: usize, material_count: usize) -> Self {
+        Self {
+            position: point.position.to_array(),
+            radius: point.radius,
+            material: material_offset as u32 + point.material.min(material_count as u32 - 1),
+            _padding: [0; 3],
+        }
+    }
+
+    /// Marks slots that don't belong to any voxel, shaders skip points with a negative radius.
+    const EMPTY: Self = Self {
+        position: [0.0; 3],
+        radius: -1.0,
+        material: 0,
+        _padding: [0; 3],
+    };
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct VoxelMaterialRaw {
+    color: [f32; 3],
+    roughness: f32,
+    emission: f32,
+    // Storage buffers align this struct to 16 bytes
+    _padding: [f32; 3],
+}
+
+impl From<&VoxelMaterial> for VoxelMaterialRaw {
+    fn from(material: &VoxelMaterial) -> Self {
+        Self {
+            color: material.color.to_array(),
+            roughness: material.roughness,
+            emission: material.emission,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelUniform {
+    point_count: u32,
+    smooth_min: u32,
+    blend_radius: f32,
+    max_steps: u32,
+    grid_origin: [f32; 3],
+    cell_size: f32,
+    grid_dims: [u32; 3],
+    grid_margin: f32,
+    index_capacity: u32,
+    gi_strength: f32,
+    reflection_max_roughness: f32,
+    reflection_distance: f32,
+}
+
+/// The placement of the uniform grid the raymarcher uses to find the points near a position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GridParams {
+    origin: Vec3,
+    cell_size: f32,
+    dims: UVec3,
+    /// Points are listed in every cell their radius plus this margin overlaps
+    margin: f32,
+}
+
+impl GridParams {
+    // Keeps the offset buffers and the prefix sum in a reasonable size
+    const MAX_DIM: u32 = 64;
+    // The prefix sum in grid.wgsl handles blocks of 256 cells
+    const MAX_SCAN_BLOCKS: usize = (Self::MAX_DIM.pow(3) / 256) as usize;
+
+    /// Fits a grid around the points with roughly one point per cell.
+    /// Cells are never smaller than the largest radius plus the margin, so every point overlaps at most 3x3x3 cells.
+    fn new<'a>(points: impl Iterator<Item = &'a VoxelPoint> + Clone, margin: f32) -> Self {
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        let mut max_reach = 0.0f32;
+        let mut count = 0;
+        for point in points {
+            let reach = point.radius + margin;
+            min = min.min(point.position - reach);
+            max = max.max(point.position + reach);
+            max_reach = max_reach.max(reach);
+            count += 1;
+        }
+        if count == 0 {
+            return Self {
+                origin: Vec3::ZERO,
+                cell_size: 1.0,
+                dims: UVec3::ONE,
+                margin,
+            };
+        }
+
+        let extent = max - min;
+        let cell_size = (extent.x * extent.y * extent.z / count as f32)
+            .cbrt()
+            .max(max_reach)
+            .max(extent.max_element() / Self::MAX_DIM as f32)
+            .max(f32::EPSILON);
+        let dims = (extent / cell_size)
+            .ceil()
+            .as_uvec3()
+            .clamp(UVec3::ONE, UVec3::splat(Self::MAX_DIM));
+
+        Self {
+            origin: min,
+            cell_size,
+            dims,
+            margin,
+        }
+    }
+
+    fn cell_count(&self) -> usize {
+        (self.dims.x * self.dims.y * self.dims.z) as usize
+    }
+
+    /// The cell containing the position, positions outside of the grid are clamped to the closest cell.
+    fn cell(&self, position: Vec3) -> IVec3 {
+        ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.dims.as_ivec3() - 1)
+    }
+
+    /// The number of cells between `cell_min` and `cell_max` the point is listed in, matching `cell_range()` in grid.wgsl.
+    fn overlapped_cells(&self, point: &VoxelPoint, cell_min: IVec3, cell_max: IVec3) -> usize {
+        let reach = point.radius + self.margin;
+        let cells = (self.cell(point.position + reach).min(cell_max)
+            - self.cell(point.position - reach).max(cell_min)
+            + 1)
+        .max(IVec3::ZERO);
+        (cells.x * cells.y * cells.z) as usize
+    }
+}
+
+/// The cells rebuilt by the next grid build and the slots of the points that can overlap them, `GridUpdate` in grid.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUpdate {
+    cell_min: [u32; 3],
+    point_offset: u32,
+    cell_dims: [u32; 3],
+    point_count: u32,
+}
+
+impl GridUpdate {
+    fn cell_count(&self) -> u32 {
+        self.cell_dims.iter().product()
+    }
+}
+
+/// The buffers of the uniform grid. They are filled by the compute passes in grid.wgsl whenever the voxels change.
+/// As long as the changed points still fit into the grid only the cells around them are rebuilt,
+/// which append their new lists to the cell indices until they run out of space and the whole grid is rebuilt.
+struct VoxelGrid {
+    params: GridParams,
+    cell_offsets: wgpu::Buffer,
+    cell_ends: wgpu::Buffer,
+    cell_indices: wgpu::Buffer,
+    /// `GridUpdate` of grid.wgsl
+    update_buffer: wgpu::Buffer,
+    /// `GridScan` of grid.wgsl
+    scan_buffer: wgpu::Buffer,
+    cell_capacity: usize,
+    index_capacity: usize,
+    /// An upper bound of the cell indices in use
+    index_end: usize,
+    bind_group: wgpu::BindGroup,
+    /// The bounds of the points that changed since the last build, at their old and new positions
+    changed: Option<Aabb>,
+    /// The largest radius of the changed points
+    changed_radius: f32,
+    /// Fits the grid to the points again and rebuilds every cell
+    refit: bool,
+    update: GridUpdate,
+    needs_build: bool,
+}
+
+impl VoxelGrid {
+    /// Marks the cells overlapped by the points to be rebuilt.
+    fn mark<'a>(&mut self, points: impl IntoIterator<Item = &'a VoxelPoint>) {
+        for point in points {
+            let bounds = Aabb {
+                min: point.position - point.radius,
+                max: point.position + point.radius,
+            };
+            self.changed = Some(
+                self.changed
+                    .map_or(bounds, |changed| changed.union(&bounds)),
+            );
+            self.changed_radius = self.changed_radius.max(point.radius);
+        }
+    }
+}
+
+/// The bounds of the spheres of the points.
+fn sphere_bounds<'a>(points: impl IntoIterator<Item = &'a VoxelPoint>) -> Option<Aabb> {
+    Aabb::from_points(
+        points
+            .into_iter()
+            .flat_map(|point| [point.position - point.radius, point.position + point.radius]),
+    )
+}
+
+/// A range of slots reserved for one volume.
+#[derive(Clone, Copy, Debug)]
+struct Slab {
+    offset: usize,
+    len: usize,
+    capacity: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Allocation {
+    points: Slab,
+    materials: Slab,
+}
+
+/// Stores all [`VoxelVolume`]s in two storage buffers, one for the points and one for the materials.
+/// Every volume owns a slab in both buffers, so updating a volume only uploads its slab as long as it doesn't outgrow it.
+pub(crate) struct VoxelStorage {
+    volumes: Vec<Option<VoxelVolume>>,
+    allocations: Vec<Option<Allocation>>,
+    /// The bounds of the points of every volume, they only grow until the volume is replaced
+    bounds: Vec<Option<Aabb>>,
+    points: GrowableBuffer<VoxelPointRaw>,
+    materials: GrowableBuffer<VoxelMaterialRaw>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    grid: VoxelGrid,
+    /// One VoxelIrradiance of gi.wgsl per point slot
+    irradiance: wgpu::Buffer,
+    irradiance_capacity: usize,
+    /// Where the irradiance of a slot comes from at the next `flush()`, as a slot of the current irradiance buffer.
+    /// `None` starts the slot over at zero, slots that aren't listed keep their irradiance.
+    irradiance_sources: HashMap<usize, Option<usize>>,
+    /// After compacting the slots that aren't listed in `irradiance_sources` start over as well
+    irradiance_remapped: bool,
+    pub gi_strength: f32,
+    pub reflections: ReflectionSettings,
+    pub smooth_min: SmoothMin,
+    pub blend_radius: f32,
+    pub max_steps: u32,
+}
+
+impl VoxelStorage {
+    // Compacting only happens once this many slots are unused
+    const COMPACT_THRESHOLD: usize = 4096;
+
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        grid_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let points = GrowableBuffer::new(
+            device,
+            "Voxel Point Buffer",
+            wgpu::BufferUsages::STORAGE,
+            vec![],
+        );
+        let materials = GrowableBuffer::new(
+            device,
+            "Voxel Material Buffer",
+            wgpu::BufferUsages::STORAGE,
+            vec![],
+        );
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Voxel Uniform Buffer"),
+            size: std::mem::size_of::<VoxelUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params = GridParams::new(std::iter::empty(), 0.0);
+        let cell_offsets = create_grid_buffer(device, "Voxel Cell Offset Buffer", 1);
+        let cell_ends = create_grid_buffer(device, "Voxel Cell End Buffer", 1);
+        let cell_indices = create_grid_buffer(device, "Voxel Cell Index Buffer", 1);
+        let update_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Voxel Grid Update Buffer"),
+            size: std::mem::size_of::<GridUpdate>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let scan_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Voxel Grid Scan Buffer"),
+            // The end of the cell indices followed by the block sums
+            size: ((1 + GridParams::MAX_SCAN_BLOCKS) * std::mem::size_of::<u32>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let grid = VoxelGrid {
+            bind_group: create_grid_bind_group(
+                device,
+                grid_layout,
+                &uniform_buffer,
+                &points,
+                [&cell_offsets, &cell_ends, &cell_indices],
+                [&update_buffer, &scan_buffer],
+            ),
+            params,
+            cell_offsets,
+            cell_ends,
+            cell_indices,
+            update_buffer,
+            scan_buffer,
+            cell_capacity: 1,
+            index_capacity: 1,
+            index_end: 0,
+            changed: None,
+            changed_radius: 0.0,
+            refit: false,
+            update: bytemuck::Zeroable::zeroed(),
+            needs_build: false,
+        };
+        let irradiance = create_irradiance_buffer(device, 1);
+        let bind_group = create_bind_group(
+            device,
+            layout,
+            &uniform_buffer,
+            &points,
+            &materials,
+            [&grid.cell_offsets, &grid.cell_ends, &grid.cell_indices],
+            &irradiance,
+        );
+
+        Self {
+            volumes: vec![],
+            allocations: vec![],
+            bounds: vec![],
+            points,
+            materials,
+            uniform_buffer,
+            bind_group,
+            grid,
+            irradiance,
+            irradiance_capacity: 1,
+            irradiance_sources: HashMap::new(),
+            irradiance_remapped: false,
+            gi_strength: 0.0,
+            reflections: ReflectionSettings::default(),
+            smooth_min: SmoothMin::default(),
+            blend_radius: 0.2,
+            max_steps: 128,
+        }
+    }
+
+    /// Binds the voxel uniform, points, materials, the grid and the irradiance for reading.
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn irradiance(&self) -> &wgpu::Buffer {
+        &self.irradiance
+    }
+
+    /// The number of point slots in use, including empty ones. Slots after this may contain stale data.
+    pub fn point_count(&self) -> u32 {
+        self.points.len() as u32
+    }
+
+    pub fn get(&self, volume_id: usize) -> &VoxelVolume {
+        self.volumes[volume_id].as_ref().unwrap()
+    }
+
+    /// All volumes together with their ids.
+    pub fn volumes(&self) -> impl Iterator<Item = (usize, &VoxelVolume)> {
+        self.volumes
+            .iter()
+            .enumerate()
+            .filter_map(|(volume_id, volume)| Some((volume_id, volume.as_ref()?)))
+    }
+
+    pub fn add(&mut self, volume: VoxelVolume) -> usize {
+        let index = match self.volumes.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.volumes.push(None);
+                self.allocations.push(None);
+                self.bounds.push(None);
+                self.volumes.len() - 1
+            }
+        };
+        self.grid.mark(&volume.points);
+        self.bounds[index] = sphere_bounds(&volume.points);
+        self.volumes[index] = Some(volume);
+        self.upload(index);
+
+        index
+    }
+
+    pub fn update(&mut self, volume_id: usize, volume: VoxelVolume) {
+        if let Some(previous) = &self.volumes[volume_id] {
+            self.grid.mark(&previous.points);
+        }
+        self.grid.mark(&volume.points);
+        self.bounds[volume_id] = sphere_bounds(&volume.points);
+        self.volumes[volume_id] = Some(volume);
+        self.upload(volume_id);
+    }
+
+    pub fn remove(&mut self, volume_id: usize) {
+        if let Some(previous) = self.volumes[volume_id].take() {
+            self.grid.mark(&previous.points);
+        }
+        self.bounds[volume_id] = None;
+        if let Some(allocation) = self.allocations[volume_id].take() {
+            self.free(allocation);
+        }
+    }
+
+    /// Overwrites a single point of a volume, only its slot is uploaded.
+    pub fn set_point(&mut self, volume_id: usize, index: usize, point: VoxelPoint) {
+        let previous = std::mem::replace(
+            &mut self.volumes[volume_id].as_mut().unwrap().points[index],
+            point,
+        );
+        self.grid.mark([&previous, &point]);
+        self.grow_bounds(volume_id, &point);
+        self.write_point(volume_id, index);
+    }
+
+    /// Appends a point to a volume, the volume is only uploaded again if it outgrows its slab.
+    pub fn push_point(&mut self, volume_id: usize, point: VoxelPoint) {
+        let volume = self.volumes[volume_id].as_mut().unwrap();
+        volume.points.push(point);
+        let index = volume.points.len() - 1;
+        self.grow_bounds(volume_id, &point);
+        let allocation = self.allocations[volume_id].as_mut().unwrap();
+        if index < allocation.points.capacity {
+            allocation.points.len = index + 1;
+            let slot = allocation.points.offset + index;
+            self.reset_irradiance(slot..slot + 1);
+            self.write_point(volume_id, index);
+            self.grid.mark([&point]);
+        } else {
+            self.upload(volume_id);
+            // Every point moved to a new slot
+            self.grid
+                .mark(&self.volumes[volume_id].as_ref().unwrap().points);
+        }
+    }
+
+    /// Removes a point of a volume and replaces it with the last point of the volume, like [`Vec::swap_remove`].
+    pub fn swap_remove_point(&mut self, volume_id: usize, index: usize) -> VoxelPoint {
+        let volume = self.volumes[volume_id].as_mut().unwrap();
+        let point = volume.points.swap_remove(index);
+        let len = volume.points.len();
+        self.grid.mark([&point]);
+        if index < len {
+            // The last point moved to the slot of the removed one
+            self.grid.mark([&volume.points[index]]);
+            self.write_point(volume_id, index);
+        }
+        let allocation = self.allocations[volume_id].as_mut().unwrap();
+        allocation.points.len = len;
+        let offset = allocation.points.offset;
+        self.points.set(offset + len, VoxelPointRaw::EMPTY);
+        if index < len {
+            self.move_irradiance(offset + len, offset + index, 1);
+        }
+
+        point
+    }
+
+    fn grow_bounds(&mut self, volume_id: usize, point: &VoxelPoint) {
+        let bounds = sphere_bounds([point]).unwrap();
+        let volume_bounds = &mut self.bounds[volume_id];
+        *volume_bounds =
+            Some(volume_bounds.map_or(bounds, |volume_bounds| volume_bounds.union(&bounds)));
+    }
+
+    fn write_point(&mut self, volume_id: usize, index: usize) {
+        let allocation = self.allocations[volume_id].unwrap();
+        let point = &self.volumes[volume_id].as_ref().unwrap().points[index];
+        let raw = VoxelPointRaw::new(point, allocation.materials.offset, allocation.materials.len);
+        self.points.set(allocation.points.offset + index, raw);
+    }
+
+    /// The distance up to which other points influence the surface, which depends on the smooth minimum.
+    fn grid_margin(&self) -> f32 {
+        match self.smooth_min {
+            SmoothMin::Polynomial => self.blend_radius,
+            // Exponential blending never reaches zero, past four blend radii the influence is below 1/16
+            SmoothMin::Exponential => self.blend_radius * 4.0,
+        }
+    }
+
+    /// Uploads pending changes. Returns `true` if a buffer had to be recreated, in which case the bind groups are recreated as well.
+    pub fn flush(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        grid_layout: &wgpu::BindGroupLayout,
+    ) -> bool {
+        let live_points = self
+            .allocations
+            .iter()
+            .flatten()
+            .map(|allocation| allocation.points.len)
+            .sum::<usize>();
+        let unused_points = self.points.len() - live_points;
+        if unused_points > Self::COMPACT_THRESHOLD && unused_points > live_points {
+            self.compact();
+        }
+
+        let grid_recreated = self.prepare_grid(device, queue);
+
+        let params = self.grid.params;
+        let uniform = VoxelUniform {
+            point_count: self.point_count(),
+            smooth_min: self.smooth_min as u32,
+            blend_radius: self.blend_radius,
+            max_steps: self.max_steps,
+            grid_origin: params.origin.to_array(),
+            cell_size: params.cell_size,
+            grid_dims: params.dims.to_array(),
+            grid_margin: params.margin,
+            index_capacity: self.grid.index_capacity as u32,
+            gi_strength: self.gi_strength,
+            reflection_max_roughness: if self.reflections.enabled {
+                self.reflections.max_roughness
+            } else {
+                -1.0
+            },
+            reflection_distance: self.reflections.max_distance,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let irradiance_recreated = self.flush_irradiance(device, queue);
+
+        let points_recreated = self.points.flush(device, queue);
+        let materials_recreated = self.materials.flush(device, queue);
+        let recreated =
+            points_recreated || materials_recreated || grid_recreated || irradiance_recreated;
+        if recreated {
+            let grid_buffers = [
+                &self.grid.cell_offsets,
+                &self.grid.cell_ends,
+                &self.grid.cell_indices,
+            ];
+            self.bind_group = create_bind_group(
+                device,
+                layout,
+                &self.uniform_buffer,
+                &self.points,
+                &self.materials,
+                grid_buffers,
+                &self.irradiance,
+            );
+            self.grid.bind_group = create_grid_bind_group(
+                device,
+                grid_layout,
+                &self.uniform_buffer,
+                &self.points,
+                grid_buffers,
+                [&self.grid.update_buffer, &self.grid.scan_buffer],
+            );
+        }
+
+        recreated
+    }
+
+    /// Returns `true` once after the voxels changed, in which case the grid has to be rebuilt with `build_grid()`.
+    pub fn take_grid_build(&mut self) -> bool {
+        std::mem::take(&mut self.grid.needs_build)
+    }
+
+    /// Decides which cells the next build updates, fitting the grid to the current points again if the changed points don't fit into it.
+    /// Returns `true` if a grid buffer had to be recreated.
+    fn prepare_grid(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let margin = self.grid_margin();
+        if margin != self.grid.params.margin {
+            self.grid.refit = true;
+        }
+        let changed = self.grid.changed.take();
+        let changed_radius = std::mem::take(&mut self.grid.changed_radius);
+        if !std::mem::take(&mut self.grid.refit) {
+            let Some(changed) = changed else {
+                return false;
+            };
+            if let Some(update) = self.grid_update(changed, changed_radius) {
+                self.set_grid_update(queue, update);
+                return false;
+            }
+        }
+
+        let points = self
+            .volumes
+            .iter()
+            .flatten()
+            .flat_map(|volume| &volume.points);
+        let params = GridParams::new(points.clone(), margin);
+        let (cell_min, cell_max) = (IVec3::ZERO, params.dims.as_ivec3() - 1);
+        // The GPU might round a few points into neighbouring cells, so leave some room
+        let index_count = points
+            .map(|point| params.overlapped_cells(point, cell_min, cell_max))
+            .sum::<usize>();
+        let index_count = index_count + index_count / 8 + 64;
+        self.grid.params = params;
+        self.grid.index_end = index_count;
+        // The new lists start at the beginning of the cell indices
+        queue.write_buffer(&self.grid.scan_buffer, 0, bytemuck::cast_slice(&[0u32]));
+        self.set_grid_update(
+            queue,
+            GridUpdate {
+                cell_min: [0; 3],
+                point_offset: 0,
+                cell_dims: params.dims.to_array(),
+                point_count: self.point_count(),
+            },
+        );
+
+        let mut recreated = false;
+        if params.cell_count() > self.grid.cell_capacity {
+            self.grid.cell_capacity = params.cell_count().max(self.grid.cell_capacity * 2);
+            self.grid.cell_offsets =
+                create_grid_buffer(device, "Voxel Cell Offset Buffer", self.grid.cell_capacity);
+            self.grid.cell_ends =
+                create_grid_buffer(device, "Voxel Cell End Buffer", self.grid.cell_capacity);
+            recreated = true;
+        }
+        // Leaves as much room for the lists of rebuilt cells as the initial lists take
+        if index_count * 2 > self.grid.index_capacity {
+            self.grid.index_capacity = (index_count * 2).max(self.grid.index_capacity * 2);
+            self.grid.cell_indices =
+                create_grid_buffer(device, "Voxel Cell Index Buffer", self.grid.index_capacity);
+            recreated = true;
+        }
+
+        recreated
+    }
+
+    /// The cells around the changed points together with the slots of all points that can overlap them,
+    /// or `None` if the grid has to be fitted to the points again.
+    fn grid_update(&mut self, changed: Aabb, changed_radius: f32) -> Option<GridUpdate> {
+        let params = self.grid.params;
+        // Larger points would overlap more cells than the grid was sized for
+        if changed_radius + params.margin > params.cell_size {
+            return None;
+        }
+        let grid_max = params.origin + params.dims.as_vec3() * params.cell_size;
+        let (changed_min, changed_max) = (changed.min - params.margin, changed.max + params.margin);
+        if changed_min.cmplt(params.origin).any() || changed_max.cmpgt(grid_max).any() {
+            return None;
+        }
+
+        let cell_min = params.cell(changed_min);
+        let cell_max = params.cell(changed_max);
+        let cells = Aabb {
+            min: params.origin + cell_min.as_vec3() * params.cell_size,
+            max: params.origin + (cell_max + 1).as_vec3() * params.cell_size,
+        };
+        let mut slots: Option<std::ops::Range<usize>> = None;
+        let mut index_count = 0;
+        for (volume_id, volume) in self.volumes() {
+            let (Some(bounds), Some(allocation)) =
+                (self.bounds[volume_id], self.allocations[volume_id])
+            else {
+                continue;
+            };
+            let reach = Aabb {
+                min: bounds.min - params.margin,
+                max: bounds.max + params.margin,
+            };
+            if !reach.intersects(&cells) {
+                continue;
+            }
+            let slab = allocation.points.offset..allocation.points.offset + allocation.points.len;
+            slots = Some(slots.map_or(slab.clone(), |slots| {
+                slots.start.min(slab.start)..slots.end.max(slab.end)
+            }));
+            index_count += volume
+                .points
+                .iter()
+                .map(|point| params.overlapped_cells(point, cell_min, cell_max))
+                .sum::<usize>();
+        }
+        let index_count = index_count + index_count / 8 + 64;
+        if self.grid.index_end + index_count > self.grid.index_capacity {
+            return None;
+        }
+        self.grid.index_end += index_count;
+        let slots = slots.unwrap_or_default();
+
+        Some(GridUpdate {
+            cell_min: cell_min.as_uvec3().to_array(),
+            point_offset: slots.start as u32,
+            cell_dims: (cell_max - cell_min + 1).as_uvec3().to_array(),
+            point_count: slots.len() as u32,
+        })
+    }
+
+    fn set_grid_update(&mut self, queue: &wgpu::Queue, update: GridUpdate) {
+        queue.write_buffer(&self.grid.update_buffer, 0, bytemuck::cast_slice(&[update]));
+        self.grid.update = update;
+        self.grid.needs_build = true;
+    }
+
+    /// Lists the points in the grid cells they overlap, for the cells chosen by `prepare_grid()`.
+    /// `pipelines` are the clear, count, scan, block scan, scan apply and fill pipelines.
+    pub fn build_grid<'a>(
+        &'a self,
+        compute_pass: &mut wgpu::ComputePass<'a>,
+        pipelines: &'a [wgpu::ComputePipeline],
+    ) {
+        let cell_count = self.grid.update.cell_count();
+        let cell_workgroups = cell_count.div_ceil(64);
+        let block_workgroups = cell_count.div_ceil(256);
+        let point_workgroups = self.grid.update.point_count.div_ceil(64);
+
+        compute_pass.set_bind_group(0, &self.grid.bind_group, &[]);
+        for (pipeline, workgroups) in pipelines.iter().zip([
+            cell_workgroups,
+            point_workgroups,
+            block_workgroups,
+            1,
+            block_workgroups,
+            point_workgroups,
+        ]) {
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+    }
+
+    /// Writes a volume into its slabs, moving it to the end of the buffers if it doesn't fit anymore.
+    fn upload(&mut self, volume_id: usize) {
+        let volume = self.volumes[volume_id].as_ref().unwrap();
+        let point_count = volume.points.len();
+        let material_count = volume.materials.len().max(1);
+
+        let previous = self.allocations[volume_id];
+        let allocation = match previous {
+            Some(allocation)
+                if point_count <= allocation.points.capacity
+                    && material_count <= allocation.materials.capacity =>
+            {
+                allocation
+            }
+            previous => {
+                // Growing volumes get twice the space to make further growth cheap
+                let (point_capacity, material_capacity) = match previous {
+                    Some(allocation) => {
+                        self.free(allocation);
+                        (
+                            point_count.max(allocation.points.capacity * 2),
+                            material_count.max(allocation.materials.capacity * 2),
+                        )
+                    }
+                    None => (point_count, material_count),
+                };
+                Allocation {
+                    points: self.reserve_points(point_capacity),
+                    materials: self.reserve_materials(material_capacity),
+                }
+            }
+        };
+
+        let volume = self.volumes[volume_id].as_ref().unwrap();
+        let materials = if volume.materials.is_empty() {
+            vec![VoxelMaterialRaw::from(&VoxelMaterial::default())]
+        } else {
+            volume
+                .materials
+                .iter()
+                .map(VoxelMaterialRaw::from)
+                .collect()
+        };
+        let mut points = volume
+            .points
+            .iter()
+            .map(|point| VoxelPointRaw::new(point, allocation.materials.offset, material_count))
+            .collect::<Vec<_>>();
+        // Clear the slots a shrinking volume doesn't use anymore
+        if allocation.points.len > point_count {
+            points.resize(allocation.points.len, VoxelPointRaw::EMPTY);
+        }
+
+        self.points.write(allocation.points.offset, &points);
+        self.materials
+            .write(allocation.materials.offset, &materials);
+
+        // The irradiance follows the points into their new slots, new points start over
+        let offset = allocation.points.offset;
+        let kept = match previous {
+            Some(previous) => {
+                let kept = previous.points.len.min(point_count);
+                if previous.points.offset != offset {
+                    self.move_irradiance(previous.points.offset, offset, kept);
+                }
+                kept
+            }
+            None => 0,
+        };
+        self.reset_irradiance(offset + kept..offset + point_count);
+
+        self.allocations[volume_id] = Some(Allocation {
+            points: Slab {
+                len: point_count,
+                ..allocation.points
+            },
+            materials: Slab {
+                len: material_count,
+                ..allocation.materials
+            },
+        });
+    }
+
+    fn reserve_points(&mut self, capacity: usize) -> Slab {
+        let offset = self.points.len();
+        self.points
+            .write(offset, &vec![VoxelPointRaw::EMPTY; capacity]);
+
+        Slab {
+            offset,
+            len: 0,
+            capacity,
+        }
+    }
+
+    fn reserve_materials(&mut self, capacity: usize) -> Slab {
+        let offset = self.materials.len();
+        self.materials.write(
+            offset,
+            &vec![VoxelMaterialRaw::from(&VoxelMaterial::default()); capacity],
+        );
+
+        Slab {
+            offset,
+            len: 0,
+            capacity,
+        }
+    }
+
+    fn free(&mut self, allocation: Allocation) {
+        self.points.write(
+            allocation.points.offset,
+            &vec![VoxelPointRaw::EMPTY; allocation.points.len],
+        );
+    }
+
+    /// Moves all volumes next to each other, which requires uploading everything again.
+    fn compact(&mut self) {
+        self.grid.refit = true;
+        self.points.replace(&[]);
+        self.materials.replace(&[]);
+        let previous = std::mem::replace(&mut self.allocations, vec![None; self.volumes.len()]);
+
+        for volume_id in 0..self.volumes.len() {
+            if self.volumes[volume_id].is_some() {
+                self.upload(volume_id);
+            }
+        }
+
+        // Only the irradiance of the points that are still there is kept
+        let mut sources = HashMap::new();
+        for (previous, allocation) in previous.iter().zip(&self.allocations) {
+            let (Some(previous), Some(allocation)) = (previous, allocation) else {
+                continue;
+            };
+            for index in 0..previous.points.len.min(allocation.points.len) {
+                sources.insert(
+                    allocation.points.offset + index,
+                    self.irradiance_source(previous.points.offset + index),
+                );
+            }
+        }
+        self.irradiance_sources = sources;
+        self.irradiance_remapped = true;
+    }
+
+    /// Where the irradiance of the slot will come from at the next `flush()`.
+    fn irradiance_source(&self, slot: usize) -> Option<usize> {
+        self.irradiance_sources
+            .get(&slot)
+            .copied()
+            .unwrap_or(Some(slot))
+    }
+
+    fn move_irradiance(&mut self, from: usize, to: usize, len: usize) {
+        let sources = (from..from + len)
+            .map(|slot| self.irradiance_source(slot))
+            .collect::<Vec<_>>();
+        self.irradiance_sources.extend((to..to + len).zip(sources));
+    }
+
+    fn reset_irradiance(&mut self, slots: Range<usize>) {
+        // Slots past the end of the buffer start at zero anyway
+        let slots = slots.start..slots.end.min(self.irradiance_capacity);
+        self.irradiance_sources
+            .extend(slots.map(|slot| (slot, None)));
+    }
+
+    /// Moves and resets the irradiance of the slots in `irradiance_sources`, growing the buffer if there are more slots than it holds.
+    /// Returns `true` if the buffer had to be recreated.
+    fn flush_irradiance(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let grow = self.points.len() > self.irradiance_capacity;
+        let remapped = std::mem::take(&mut self.irradiance_remapped);
+        if self.irradiance_sources.is_empty() && !grow && !remapped {
+            return false;
+        }
+
+        // Slots past the end of the buffer never had any irradiance
+        let old_capacity = self.irradiance_capacity;
+        let mut sources = self
+            .irradiance_sources
+            .drain()
+            .map(|(slot, source)| (slot, source.filter(|&source| source < old_capacity)))
+            .collect::<Vec<_>>();
+        sources.sort_unstable_by_key(|&(slot, _)| slot);
+        let runs = irradiance_runs(&sources);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Voxel Irradiance Encoder"),
+        });
+        let recreated = grow || remapped;
+        if recreated {
+            if grow {
+                self.irradiance_capacity = self.points.len().max(self.irradiance_capacity * 2);
+            }
+            let irradiance = create_irradiance_buffer(device, self.irradiance_capacity);
+            if !remapped {
+                encoder.copy_buffer_to_buffer(
+                    &self.irradiance,
+                    0,
+                    &irradiance,
+                    0,
+                    self.irradiance.size(),
+                );
+            }
+            for run in &runs {
+                match run.source {
+                    Some(source) => encoder.copy_buffer_to_buffer(
+                        &self.irradiance,
+                        source as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                        &irradiance,
+                        run.slot as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                        run.len as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                    ),
+                    None => run.clear(&mut encoder, &irradiance),
+                }
+            }
+            self.irradiance = irradiance;
+        } else {
+            // A copy can't read and write the same buffer, so the moved irradiance is copied into a scratch buffer first
+            let moved = runs
+                .iter()
+                .filter(|run| run.source.is_some())
+                .map(|run| run.len)
+                .sum::<usize>();
+            let scratch = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Voxel Irradiance Scratch Buffer"),
+                size: moved.max(1) as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let mut offset = 0;
+            for run in &runs {
+                if let Some(source) = run.source {
+                    encoder.copy_buffer_to_buffer(
+                        &self.irradiance,
+                        source as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                        &scratch,
+                        offset,
+                        run.len as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                    );
+                    offset += run.len as wgpu::BufferAddress * IRRADIANCE_SIZE;
+                }
+            }
+            let mut offset = 0;
+            for run in &runs {
+                match run.source {
+                    Some(_) => {
+                        encoder.copy_buffer_to_buffer(
+                            &scratch,
+                            offset,
+                            &self.irradiance,
+                            run.slot as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                            run.len as wgpu::BufferAddress * IRRADIANCE_SIZE,
+                        );
+                        offset += run.len as wgpu::BufferAddress * IRRADIANCE_SIZE;
+                    }
+                    None => run.clear(&mut encoder, &self.irradiance),
+                }
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        recreated
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    points: &GrowableBuffer<VoxelPointRaw>,
+    materials: &GrowableBuffer<VoxelMaterialRaw>,
+    [cell_offsets, cell_ends, cell_indices]: [&wgpu::Buffer; 3],
+    irradiance: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: points.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: materials.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: cell_offsets.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: cell_ends.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: cell_indices.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: irradiance.as_entire_binding(),
+            },
+        ],
+        label: Some("voxel_bind_group"),
+    })
+}
+
+fn create_grid_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    points: &GrowableBuffer<VoxelPointRaw>,
+    [cell_offsets, cell_ends, cell_indices]: [&wgpu::Buffer; 3],
+    [update_buffer, scan_buffer]: [&wgpu::Buffer; 2],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: points.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: cell_offsets.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: cell_ends.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: cell_indices.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: update_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: scan_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("voxel_grid_bind_group"),
+    })
+}
+
+fn create_grid_buffer(device: &wgpu::Device, label: &str, len: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (len * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+// VoxelIrradiance in voxel_types.wgsl is 32 bytes
+const IRRADIANCE_SIZE: wgpu::BufferAddress = 32;
+
+fn create_irradiance_buffer(device: &wgpu::Device, len: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Voxel Irradiance Buffer"),
+        size: len as wgpu::BufferAddress * IRRADIANCE_SIZE,
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Consecutive slots whose irradiance comes from consecutive slots, or starts over if `source` is `None`.
+struct IrradianceRun {
+    slot: usize,
+    source: Option<usize>,
+    len: usize,
+}
+
+impl IrradianceRun {
+    fn clear(&self, encoder: &mut wgpu::CommandEncoder, irradiance: &wgpu::Buffer) {
+        encoder.clear_buffer(
+            irradiance,
+            self.slot as wgpu::BufferAddress * IRRADIANCE_SIZE,
+            NonZeroU64::new(self.len as wgpu::BufferAddress * IRRADIANCE_SIZE),
+        );
+    }
+}
+
+/// Groups the sources sorted by slot into runs, so every run takes a single copy.
+fn irradiance_runs(sources: &[(usize, Option<usize>)]) -> Vec<IrradianceRun> {
+    let mut runs: Vec<IrradianceRun> = vec![];
+    for &(slot, source) in sources {
+        if let Some(run) = runs.last_mut() {
+            let continues = match (run.source, source) {
+                (Some(run_source), Some(source)) => source == run_source + run.len,
+                (None, None) => true,
+                _ => false,
+            };
+            if continues && slot == run.slot + run.len {
+                run.len += 1;
+                continue;
+            }
+        }
+        runs.push(IrradianceRun {
+            slot,
+            source,
+            len: 1,
+        });
+    }
+    runs
+}
@@ -0,0 +1,212 @@
+// Signed distance functions for the voxel scene
+// The shader using these has to declare the bindings:
+//     var<uniform> voxels: Voxels;
+//     var<storage, read> voxel_points: array<VoxelPoint>;
+//     var<storage, read> voxel_materials: array<VoxelMaterial>;
+//     var<storage, read> cell_offsets: array<u32>;
+//     var<storage, read> cell_ends: array<u32>;
+//     var<storage, read> cell_indices: array<u32>;
+//     var<storage, read> voxel_irradiance: array<VoxelIrradiance>;
+
+#include "voxel_types.wgsl"
+
+struct VoxelSurface {
+    distance: f32,
+    color: vec3<f32>,
+    roughness: f32,
+    emission: f32,
+}
+
+const SMOOTH_MIN_POLYNOMIAL: u32 = 0u;
+const SMOOTH_MIN_EXPONENTIAL: u32 = 1u;
+const VOXEL_HIT_DISTANCE: f32 = 0.001;
+
+fn material_surface(distance: f32, material: VoxelMaterial) -> VoxelSurface {
+    return VoxelSurface(distance, material.color, material.roughness, material.emission);
+}
+
+fn mix_surface(a: VoxelSurface, b: VoxelSurface, t: f32) -> VoxelSurface {
+    return VoxelSurface(
+        mix(a.distance, b.distance, t),
+        mix(a.color, b.color, t),
+        mix(a.roughness, b.roughness, t),
+        mix(a.emission, b.emission, t),
+    );
+}
+
+fn scale_surface(surface: VoxelSurface, scale: f32) -> VoxelSurface {
+    return VoxelSurface(surface.distance, surface.color * scale, surface.roughness * scale, surface.emission * scale);
+}
+
+fn add_surface(a: VoxelSurface, b: VoxelSurface) -> VoxelSurface {
+    return VoxelSurface(a.distance, a.color + b.color, a.roughness + b.roughness, a.emission + b.emission);
+}
+
+// Blends the surface of a single voxel into the accumulated surface using the polynomial smooth minimum
+fn smin_polynomial(accumulated: VoxelSurface, voxel: VoxelSurface, k: f32) -> VoxelSurface {
+    let h = clamp(0.5 + 0.5 * (accumulated.distance - voxel.distance) / k, 0.0, 1.0);
+    var surface = mix_surface(accumulated, voxel, h);
+    surface.distance -= k * h * (1.0 - h);
+    return surface;
+}
+
+fn voxel_point_surface(index: u32, p: vec3<f32>) -> VoxelSurface {
+    let point = voxel_points[index];
+    return material_surface(length(p - point.position) - point.radius, voxel_materials[point.material]);
+}
+
+fn grid_cell(p: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor((p - voxels.grid_origin) / voxels.cell_size));
+}
+
+fn is_in_grid(cell: vec3<i32>) -> bool {
+    return all(cell >= vec3<i32>(0)) && all(cell < vec3<i32>(voxels.grid_dims));
+}
+
+fn cell_index(cell: vec3<i32>) -> u32 {
+    let c = vec3<u32>(cell);
+    return c.x + voxels.grid_dims.x * (c.y + voxels.grid_dims.y * c.z);
+}
+
+// Evaluates the blended distance to the voxel points listed in the grid cell containing p.
+// Points that aren't listed are at least the grid margin away from the cell, empty cells return a distance of 1e30.
+fn voxel_surface(p: vec3<f32>) -> VoxelSurface {
+    let k = max(voxels.blend_radius, 0.0001);
+
+    let cell = grid_cell(p);
+    if !is_in_grid(cell) {
+        return VoxelSurface(1e30, vec3<f32>(0.0), 1.0, 0.0);
+    }
+    let start = cell_offsets[cell_index(cell)];
+    let end = min(cell_ends[cell_index(cell)], voxels.index_capacity);
+
+    if voxels.smooth_min == SMOOTH_MIN_EXPONENTIAL {
+        // exp2(-d / k) is summed relative to the closest distance to stay in range
+        var nearest = 1e30;
+        var weight = 0.0;
+        var sum = VoxelSurface(0.0, vec3<f32>(0.0), 0.0, 0.0);
+        for (var i = start; i < end; i++) {
+            let voxel = voxel_point_surface(cell_indices[i], p);
+            if voxel.distance < nearest {
+                let rescale = exp2((voxel.distance - nearest) / k);
+                weight *= rescale;
+                sum = scale_surface(sum, rescale);
+                nearest = voxel.distance;
+            }
+            let w = exp2((nearest - voxel.distance) / k);
+            weight += w;
+            sum = add_surface(sum, scale_surface(voxel, w));
+        }
+        if weight == 0.0 {
+            return VoxelSurface(1e30, vec3<f32>(0.0), 1.0, 0.0);
+        }
+        var surface = scale_surface(sum, 1.0 / weight);
+        surface.distance = nearest - k * log2(weight);
+        return surface;
+    }
+
+    var surface = VoxelSurface(1e30, vec3<f32>(0.0), 1.0, 0.0);
+    for (var i = start; i < end; i++) {
+        surface = smin_polynomial(surface, voxel_point_surface(cell_indices[i], p), k);
+    }
+    return surface;
+}
+
+fn voxel_distance(p: vec3<f32>) -> f32 {
+    return voxel_surface(p).distance;
+}
+
+fn voxel_normal(p: vec3<f32>) -> vec3<f32> {
+    // Tetrahedron technique for the gradient
+    let e = vec2<f32>(1.0, -1.0) * 0.5773 * 0.0005;
+    return normalize(
+        e.xyy * voxel_distance(p + e.xyy)
+        + e.yyx * voxel_distance(p + e.yyx)
+        + e.yxy * voxel_distance(p + e.yxy)
+        + e.xxx * voxel_distance(p + e.xxx)
+    );
+}
+
+// Returns the distances along the ray where it enters and exits the box
+fn ray_box(origin: vec3<f32>, inv_direction: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> vec2<f32> {
+    let t0 = (box_min - origin) * inv_direction;
+    let t1 = (box_max - origin) * inv_direction;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    return vec2<f32>(max(max(t_min.x, t_min.y), t_min.z), min(min(t_max.x, t_max.y), t_max.z));
+}
+
+// Sphere traces along the ray through the grid, returns the distance of the hit or -1.0
+fn voxel_march(origin: vec3<f32>, direction: vec3<f32>, max_distance: f32) -> f32 {
+    let inv_direction = 1.0 / direction;
+    let grid_max = voxels.grid_origin + vec3<f32>(voxels.grid_dims) * voxels.cell_size;
+    let grid_t = ray_box(origin, inv_direction, voxels.grid_origin, grid_max);
+    let end = min(grid_t.y, max_distance);
+
+    var t = max(grid_t.x, 0.0);
+    for (var i = 0u; i < voxels.max_steps; i++) {
+        if t > end {
+            break;
+        }
+        let p = origin + direction * t;
+        let distance = voxel_distance(p);
+        if distance < VOXEL_HIT_DISTANCE * max(t, 1.0) {
+            return t;
+        }
+
+        // Unlisted points are at least the margin away from the cell, which allows skipping empty cells entirely
+        let cell_min = voxels.grid_origin + vec3<f32>(grid_cell(p)) * voxels.cell_size;
+        let cell_exit = ray_box(p, inv_direction, cell_min, cell_min + voxels.cell_size).y;
+        t += max(min(distance, cell_exit + voxels.grid_margin), voxels.cell_size * 0.001);
+    }
+    return -1.0;
+}
+
+// Blends the irradiance of the voxels listed in the grid cell containing p, weighted by their distance
+fn voxel_gi(p: vec3<f32>) -> VoxelIrradiance {
+    var irradiance = VoxelIrradiance(vec3<f32>(0.0), 0.0, vec3<f32>(0.0));
+    let cell = grid_cell(p);
+    if !is_in_grid(cell) {
+        return irradiance;
+    }
+    let start = cell_offsets[cell_index(cell)];
+    let end = min(cell_ends[cell_index(cell)], voxels.index_capacity);
+
+    let k = max(voxels.blend_radius, voxels.cell_size * 0.01);
+    var weight = 0.0;
+    for (var i = start; i < end; i++) {
+        let index = cell_indices[i];
+        let point = voxel_points[index];
+        let w = exp2(-max(length(p - point.position) - point.radius, 0.0) / k);
+        irradiance.indirect += voxel_irradiance[index].indirect * w;
+        irradiance.radiance += voxel_irradiance[index].radiance * w;
+        weight += w;
+    }
+    if weight > 0.0 {
+        irradiance.indirect /= weight;
+        irradiance.radiance /= weight;
+    }
+    return irradiance;
+}
+
+// The light the voxels near p reflect onto a diffuse surface with the given normal.
+// Every voxel is treated as a sphere emitting its radiance, nearby voxels are found through the grid.
+fn voxel_bounce(p: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let cell = grid_cell(p);
+    if voxels.gi_strength <= 0.0 || !is_in_grid(cell) {
+        return vec3<f32>(0.0);
+    }
+    let start = cell_offsets[cell_index(cell)];
+    let end = min(cell_ends[cell_index(cell)], voxels.index_capacity);
+
+    var bounce = vec3<f32>(0.0);
+    for (var i = start; i < end; i++) {
+        let index = cell_indices[i];
+        let point = voxel_points[index];
+        let to_point = point.position - p;
+        let distance_squared = max(dot(to_point, to_point), point.radius * point.radius);
+        let cosine = max(dot(normal, normalize(to_point)), 0.0);
+        bounce += voxel_irradiance[index].radiance * cosine * point.radius * point.radius / distance_squared;
+    }
+    return bounce * voxels.gi_strength;
+}
@@ -0,0 +1,41 @@
+// The voxel scene as it is laid out in the buffers of `VoxelStorage`
+
+struct Voxels {
+    point_count: u32,
+    smooth_min: u32,
+    blend_radius: f32,
+    max_steps: u32,
+    // The uniform grid, every cell lists the points whose radius plus margin overlaps it
+    grid_origin: vec3<f32>,
+    cell_size: f32,
+    grid_dims: vec3<u32>,
+    grid_margin: f32,
+    index_capacity: u32,
+    // Scales the indirect light from the voxel irradiance, 0 when global illumination is disabled
+    gi_strength: f32,
+    // Negative when reflections are disabled
+    reflection_max_roughness: f32,
+    reflection_distance: f32,
+}
+
+struct VoxelPoint {
+    position: vec3<f32>,
+    radius: f32,
+    material: u32,
+}
+
+struct VoxelMaterial {
+    color: vec3<f32>,
+    roughness: f32,
+    emission: f32,
+}
+
+// Updated progressively by the global illumination passes
+struct VoxelIrradiance {
+    // The light arriving at the voxel from other voxels and the sky
+    indirect: vec3<f32>,
+    // How many updates the voxel received, capped by the hysteresis
+    samples: f32,
+    // The light leaving the voxel, including direct light and emission
+    radiance: vec3<f32>,
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use glam::{Quat, Vec3};
 use winit::{
     event::{Event, WindowEvent},
@@ -6,13 +8,41 @@ use winit::{
 };
 use wisp::{
     camera::{Camera, Viewport},
+    culling::CullingMode,
+    edit::{Brush, BrushOperation, BrushShape},
+    gi::GiSettings,
+    hot_reload::ReloadedAsset,
     instance::Instance,
+    model::Material,
+    reflection::ReflectionSettings,
+    reprojection::ReprojectionSettings,
+    shader::{ShaderKey, ShaderLoader},
+    texture::{SamplerSettings, Texture, TextureFiltering, TextureSlot},
+    voxel::{SmoothMin, VoxelMaterial, VoxelPoint, VoxelVolume},
     RenderState,
 };
 
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Toon {
+    bands: f32,
+    outline: f32,
+    _padding: [f32; 2],
+}
+
 fn main() {
     tracing_subscriber::fmt::init();
 
+    // Shader variants are composed from the shared snippets
+    let shaders = ShaderLoader::default();
+    let normal_mapped = shaders
+        .compose(&ShaderKey::new("shader.wgsl", ["NORMAL_MAP"]))
+        .unwrap();
+    let flat = shaders.compose(&"shader.wgsl".into()).unwrap();
+    assert!(normal_mapped.source.contains("var t_normal"));
+    assert!(!flat.source.contains("var t_normal"));
+    assert!(flat.files.iter().any(|file| file == "camera_uniform.wgsl"));
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
 
@@ -100,11 +130,23 @@ fn main() {
         })
         .collect::<Vec<_>>();
 
+    // Anisotropic filtering for the textures of the following models
+    state.set_texture_filtering(TextureFiltering::Anisotropic(8));
+    assert_eq!(state.texture_filtering(), TextureFiltering::Anisotropic(8));
+
     // Adding and deleting Models
     let model = pollster::block_on(state.load_model_instanced("cube.obj", vec![]));
     state.remove_model(model);
+    assert_eq!(state.loaded_texture_count(), 0);
     let model = pollster::block_on(state.load_model_instanced("cube.obj", instances));
 
+    // Models loaded from the same files share their textures and materials
+    let shared_model = pollster::block_on(state.load_model_instanced("cube.obj", vec![]));
+    assert_eq!(state.loaded_texture_count(), 2);
+    assert_eq!(state.loaded_material_count(), 1);
+    state.remove_model(shared_model);
+    assert_eq!(state.loaded_texture_count(), 2);
+
     // Pushing an Instance
     state.push_instance(
         model,
@@ -114,6 +156,280 @@ fn main() {
         },
     );
 
+    // Hiding and showing Instances, the last visible Instance takes over the slot of a hidden one
+    let instance_count = state.visible_instance_count(model);
+    let last = instance_count as usize - 1;
+    state.set_instance_visible(model, 0, false);
+    state.set_instance_visible(model, 0, false);
+    assert!(!state.is_instance_visible(model, 0));
+    assert!(state.is_instance_visible(model, last));
+    assert_eq!(state.visible_instance_count(model), instance_count - 1);
+    state.set_instance_visible(model, last, false);
+    assert!(!state.is_instance_visible(model, last));
+    assert_eq!(state.visible_instance_count(model), instance_count - 2);
+    state.set_instance_visible(model, 0, true);
+    state.set_instance_visible(model, last, true);
+    assert!(state.is_instance_visible(model, 0));
+    assert!(state.is_instance_visible(model, last));
+    assert_eq!(state.visible_instance_count(model), instance_count);
+
+    // Replacing all Instances of a Model at once
+    let bulk_model = pollster::block_on(state.load_model_instanced("cube.obj", vec![]));
+    state.set_instances(
+        bulk_model,
+        &(0..4)
+            .map(|i| Instance {
+                position: Vec3::new(i as f32 * 3.0, 3.0, 0.0),
+                rotation: Quat::IDENTITY,
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    // Tiling pixel art diffuse textures with smooth normal maps
+    pollster::block_on(state.load_model_with_sampler(
+        "cube.obj",
+        vec![Instance {
+            position: Vec3::new(0.0, -3.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }],
+        |_material, slot| match slot {
+            TextureSlot::Diffuse => SamplerSettings::repeat(TextureFiltering::Nearest),
+            TextureSlot::Normal => SamplerSettings::default(),
+        },
+    ));
+
+    // Drawing a Model with a custom material
+    state.add_shader_source("toon.wgsl", include_str!("toon.wgsl"));
+    let toon = pollster::block_on(state.add_material_pipeline(
+        "toon.wgsl".into(),
+        Toon {
+            bands: 3.0,
+            outline: 0.2,
+            _padding: [0.0; 2],
+        },
+    ))
+    .unwrap();
+    state.set_model_material_pipeline(bulk_model, Some(toon));
+    assert_eq!(state.model_material_pipeline(bulk_model), Some(toon));
+    state
+        .set_material_uniform(
+            toon,
+            Toon {
+                bands: 4.0,
+                outline: 0.3,
+                _padding: [0.0; 2],
+            },
+        )
+        .unwrap();
+    assert!(state.set_material_uniform(toon, 0.0f32).is_err());
+    // The uniform is smaller than the one toon.wgsl declares
+    assert!(pollster::block_on(state.add_material_pipeline("toon.wgsl".into(), 0.0f32)).is_err());
+    state.add_shader_source("broken.wgsl", "fn fs_main( {");
+    assert!(pollster::block_on(state.add_material_pipeline("broken.wgsl".into(), 0.0f32)).is_err());
+
+    // Block compressed KTX2 and DDS textures and an HDR texture
+    pollster::block_on(state.load_model_instanced(
+        "plane.obj",
+        vec![Instance {
+            position: Vec3::new(0.0, -2.0, 0.0),
+            rotation: Quat::IDENTITY,
+        }],
+    ));
+
+    // HDR textures need a floating point format and materials refuse the unfilterable Rgba32Float
+    let hdr_image = image::Rgba32FImage::new(4, 4);
+    let sampler = Arc::new(
+        state
+            .device()
+            .create_sampler(&SamplerSettings::default().descriptor()),
+    );
+    let hdr_texture = |format| {
+        Texture::from_hdr_image(
+            state.device(),
+            state.queue(),
+            &hdr_image,
+            None,
+            format,
+            sampler.clone(),
+        )
+    };
+    assert!(hdr_texture(wgpu::TextureFormat::Rgba8Unorm).is_err());
+    let unfilterable = Arc::new(hdr_texture(wgpu::TextureFormat::Rgba32Float).unwrap());
+    assert!(Material::new(
+        state.device(),
+        "unfilterable",
+        unfilterable.clone(),
+        unfilterable,
+        0.5,
+        state.texture_bind_group_layout(),
+    )
+    .is_err());
+
+    // Cubemaps and texture arrays, empty ones are refused
+    let panorama = image::DynamicImage::ImageRgba32F(image::Rgba32FImage::new(64, 32));
+    let cubemap = Texture::cubemap_from_equirectangular(
+        state.device(),
+        state.queue(),
+        &panorama,
+        16,
+        None,
+        sampler.clone(),
+    )
+    .unwrap();
+    assert_eq!(cubemap.view_dimension, wgpu::TextureViewDimension::Cube);
+    assert_eq!(cubemap.texture.size().depth_or_array_layers, 6);
+    assert!(Texture::cubemap_from_equirectangular(
+        state.device(),
+        state.queue(),
+        &panorama,
+        0,
+        None,
+        sampler.clone(),
+    )
+    .is_err());
+
+    let layer = image::DynamicImage::ImageRgba8(image::RgbaImage::new(8, 8));
+    let array = Texture::array_from_images(
+        state.device(),
+        state.queue(),
+        &[&layer, &layer, &layer],
+        None,
+        false,
+        sampler.clone(),
+    )
+    .unwrap();
+    assert_eq!(array.view_dimension, wgpu::TextureViewDimension::D2Array);
+    assert_eq!(array.texture.size().depth_or_array_layers, 3);
+    let empty = image::DynamicImage::ImageRgba8(image::RgbaImage::new(0, 0));
+    assert!(Texture::array_from_images(
+        state.device(),
+        state.queue(),
+        &[&empty],
+        None,
+        false,
+        sampler,
+    )
+    .is_err());
+
+    // Adding, updating and removing voxel volumes
+    let voxel_points = (0..8)
+        .map(|i| VoxelPoint {
+            position: Vec3::new(i as f32 * 0.5, 5.0, 0.0),
+            radius: 0.3 + i as f32 * 0.05,
+            material: i % 2,
+        })
+        .collect::<Vec<_>>();
+    let voxel_materials = vec![
+        VoxelMaterial {
+            color: Vec3::new(1.0, 0.2, 0.2),
+            ..Default::default()
+        },
+        VoxelMaterial {
+            color: Vec3::new(0.2, 0.2, 1.0),
+            roughness: 0.2,
+            ..Default::default()
+        },
+    ];
+    let volume = state.add_voxel_volume(VoxelVolume::new(
+        voxel_points.clone(),
+        voxel_materials.clone(),
+    ));
+    state.remove_voxel_volume(volume);
+    let volume = state.add_voxel_volume(VoxelVolume::new(voxel_points, voxel_materials));
+    let mut grown_volume = state.get_voxel_volume(volume).clone();
+    grown_volume.points.push(VoxelPoint {
+        position: Vec3::new(0.0, 5.5, 0.0),
+        radius: 0.5,
+        material: 0,
+    });
+    state.update_voxel_volume(volume, grown_volume);
+    // Importing a MagicaVoxel file
+    let vox_volume =
+        pollster::block_on(state.load_voxel_volume("shapes.vox", 0.25, false)).unwrap();
+    let solid_points = state.get_voxel_volume(vox_volume).points.len();
+    assert!(solid_points > 0);
+    // Only the shell of the solid voxels
+    let hollow_volume =
+        pollster::block_on(state.load_voxel_volume("shapes.vox", 0.25, true)).unwrap();
+    assert!(state.get_voxel_volume(hollow_volume).points.len() <= solid_points);
+    state.remove_voxel_volume(hollow_volume);
+    assert!(pollster::block_on(state.load_voxel_volume("missing.vox", 0.25, false)).is_err());
+    assert!(pollster::block_on(state.load_voxel_volume("shapes.vox", 0.0, false)).is_err());
+    // Voxelizing a mesh
+    let mut voxelized = state.voxelize_model(bulk_model, 0.25).unwrap();
+    assert!(!voxelized.points.is_empty());
+    for point in &mut voxelized.points {
+        point.position += Vec3::new(-6.0, 5.0, 0.0);
+    }
+    state.add_voxel_volume(voxelized);
+    // Editing voxels with brushes
+    let hit = state
+        .raycast_voxels(Vec3::new(0.0, 5.0, 10.0), Vec3::NEG_Z, 100.0)
+        .unwrap();
+    assert_eq!(hit.volume_id, volume);
+    assert!(state.apply_brush(
+        volume,
+        &Brush {
+            shape: BrushShape::Sphere {
+                center: hit.position,
+                radius: 0.6,
+            },
+            operation: BrushOperation::Add {
+                material: 1,
+                voxel_size: 0.25,
+            },
+        },
+    ));
+    state.apply_brush(
+        volume,
+        &Brush {
+            shape: BrushShape::Box {
+                center: Vec3::new(3.0, 5.0, 0.0),
+                half_extents: Vec3::splat(0.5),
+            },
+            operation: BrushOperation::Subtract,
+        },
+    );
+    state.apply_brush(
+        volume,
+        &Brush {
+            shape: BrushShape::Sphere {
+                center: Vec3::new(1.0, 5.0, 0.0),
+                radius: 1.0,
+            },
+            operation: BrushOperation::Paint { material: 1 },
+        },
+    );
+    let edited_points = state.get_voxel_volume(volume).points.clone();
+    while state.undo_voxel_edit() {}
+    assert!(state.redo_voxel_edit());
+    assert!(state.redo_voxel_edit());
+    assert!(state.redo_voxel_edit());
+    assert_eq!(state.get_voxel_volume(volume).points, edited_points);
+    state.set_smooth_min(SmoothMin::Polynomial, 0.3);
+    state.set_gi_settings(GiSettings {
+        points_per_frame: 1024,
+        ..state.gi_settings()
+    });
+    state.set_reflection_settings(ReflectionSettings {
+        enabled: true,
+        max_roughness: 0.8,
+        ..ReflectionSettings::default()
+    });
+
+    // Reloading edited textures, models and shaders while running
+    state.set_hot_reload(true);
+    assert!(state.hot_reload());
+    // Without a shader directory of our own the shaders are read from the crate's src/ directory
+    assert!(state.shader_dir().is_some_and(|dir| dir.ends_with("src")));
+    // Shaders in a directory of our own are watched instead, editing one rebuilds the pipelines using it
+    let shader_dir = std::env::temp_dir().join("wisp_shaders");
+    std::fs::create_dir_all(&shader_dir).unwrap();
+    let light_shader = shader_dir.join("light.wgsl");
+    std::fs::write(&light_shader, include_str!("../src/light.wgsl")).unwrap();
+    state.set_shader_dir(Some(shader_dir));
+    let mut reloaded = vec![];
+
     let mut counter = 0;
 
     let current_time = std::time::SystemTime::now();
@@ -129,9 +445,32 @@ fn main() {
             Event::AboutToWait => {
                 counter += 1;
                 if counter > 1000 {
+                    // CPU culling collects stats for every rendered camera
+                    let stats = state.culling_stats(0).unwrap();
+                    assert!(stats.instances > 0);
+                    assert!(stats.instances_culled <= stats.instances);
+                    assert!(reloaded.contains(&ReloadedAsset::Shader("light.wgsl".to_string())));
                     elwt.exit();
                 }
 
+                // Culling occluded instances with the depth of the previous frame
+                if counter == 250 {
+                    state.set_occlusion_culling(true);
+                }
+
+                // Falling back to CPU culling halfway through
+                if counter == 500 {
+                    state.set_culling_mode(CullingMode::Cpu);
+                }
+
+                // Rendering the scene at a lower rate and reprojecting it in between
+                if counter == 750 {
+                    state.set_reprojection_settings(ReprojectionSettings {
+                        enabled: true,
+                        render_rate: 20.0,
+                    });
+                }
+
                 // Updating instances
                 let instance = state.get_instance(model, 2);
                 let instance_override = Instance {
@@ -144,6 +483,19 @@ fn main() {
                 };
 
                 state.override_instance(0, 2, instance_override);
+
+                // Toggling the visibility of an instance
+                let visible = (counter / 100) % 2 == 0;
+                state.set_instance_visible(model, 3, visible);
+                assert_eq!(state.is_instance_visible(model, 3), visible);
+
+                if counter == 300 {
+                    let edited = format!("{}\n// Edited\n", include_str!("../src/light.wgsl"));
+                    std::fs::write(&light_shader, edited).unwrap();
+                }
+                for result in pollster::block_on(state.reload_changed_assets()) {
+                    reloaded.push(result.unwrap());
+                }
                 window.request_redraw();
             }
             Event::WindowEvent {
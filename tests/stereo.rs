@@ -0,0 +1,137 @@
+use glam::{Quat, Vec3};
+use wisp::{
+    camera::{Camera, CameraUniform, StereoCamera, Viewport},
+    instance::Instance,
+    stereo::StereoLayout,
+    voxel::{VoxelMaterial, VoxelPoint, VoxelVolume},
+    RenderState,
+};
+
+// Renders a stereo frame without a window and dumps both eye images
+fn main() {
+    tracing_subscriber::fmt::init();
+
+    let mut state = pollster::block_on(RenderState::new_headless(512, 256)).unwrap();
+
+    let instances = (0..5)
+        .map(|i| Instance {
+            position: Vec3::new(i as f32 * 2.0 - 4.0, 0.0, -i as f32 * 2.0),
+            rotation: Quat::IDENTITY,
+        })
+        .collect::<Vec<_>>();
+    pollster::block_on(state.load_model_instanced("cube.obj", instances));
+    state.add_voxel_volume(VoxelVolume::new(
+        vec![VoxelPoint {
+            position: Vec3::new(0.0, 2.0, 0.0),
+            radius: 0.8,
+            material: 0,
+        }],
+        vec![VoxelMaterial::default()],
+    ));
+
+    let stereo_camera = StereoCamera {
+        camera: Camera {
+            eye: (0.0, 2.0, 6.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: Vec3::Y,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            viewport: None,
+        },
+        ipd: 0.064,
+        convergence: 6.0,
+    };
+    assert!(state
+        .add_stereo_camera(
+            StereoCamera {
+                convergence: 0.0,
+                ..stereo_camera
+            },
+            StereoLayout::LayeredArray,
+        )
+        .is_err());
+    let eyes = state
+        .add_stereo_camera(stereo_camera, StereoLayout::LayeredArray)
+        .unwrap();
+    assert_ne!(eyes[0], eyes[1]);
+
+    // Custom resources can be bound with the layouts of the renderer
+    let camera_buffer = state.device().create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Custom Camera Buffer"),
+        size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    state
+        .device()
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Custom Camera Bind Group"),
+            layout: state.camera_bind_group_layout(),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+    assert!(state.camera_bind_group(eyes[0]).is_some());
+
+    for frame in 0..4 {
+        // Moving the stereo camera moves both eyes
+        state
+            .override_stereo_camera(StereoCamera {
+                camera: Camera {
+                    eye: stereo_camera.camera.eye + Vec3::X * frame as f32 * 0.1,
+                    ..stereo_camera.camera
+                },
+                ..stereo_camera
+            })
+            .unwrap();
+        state.render().unwrap();
+    }
+
+    let layers = state.stereo_texture().unwrap().size().depth_or_array_layers;
+    assert_eq!(layers, 2);
+
+    // Read from the layers of the array texture
+    let [left, right] = state.read_eye_images().unwrap();
+    assert_eq!(left.dimensions(), (256, 256));
+    assert_eq!(right.dimensions(), (256, 256));
+    assert_ne!(
+        left, right,
+        "the eyes should see the scene from different positions"
+    );
+
+    // Every camera has its own depth, so a camera drawn over the left eye doesn't occlusion cull the eye's instances
+    state.set_occlusion_culling(true);
+    for _ in 0..2 {
+        state.render().unwrap();
+    }
+    let [occluded_left, _] = state.read_eye_images().unwrap();
+    let overlay = state.add_camera(Camera {
+        // Right in front of the leftmost cube, which fills the whole viewport
+        eye: (-4.0, 0.0, 1.2).into(),
+        target: (-4.0, 0.0, 0.0).into(),
+        up: Vec3::Y,
+        fovy: std::f32::consts::FRAC_PI_2,
+        znear: 0.01,
+        zfar: 100.0,
+        viewport: Some(Viewport {
+            x: 0.0,
+            y: 0.0,
+            w: 256.0,
+            h: 256.0,
+        }),
+    });
+    state.render().unwrap();
+    state.remove_camera(overlay);
+    state.render().unwrap();
+    let [left_after_overlay, _] = state.read_eye_images().unwrap();
+    assert_eq!(
+        left_after_overlay, occluded_left,
+        "the overlapping camera shouldn't hide instances of the left eye"
+    );
+
+    let out_dir = std::env::temp_dir();
+    left.save(out_dir.join("wisp_left_eye.png")).unwrap();
+    right.save(out_dir.join("wisp_right_eye.png")).unwrap();
+}
@@ -0,0 +1,56 @@
+// A custom material shading meshes in flat bands of light
+#include "material.wgsl"
+
+struct Toon {
+    bands: f32,
+    outline: f32,
+}
+@group(0) @binding(5)
+var<uniform> toon: Toon;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize(normal_matrix * model.normal);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+    let diffuse = max(dot(normal, light_dir), 0.0);
+    let banded = floor(diffuse * toon.bands) / toon.bands;
+    let rim = step(dot(normal, view_dir), toon.outline);
+    let color = (AMBIENT_STRENGTH + banded) * light.color * object_color.rgb * (1.0 - rim);
+    return vec4<f32>(color, object_color.a);
+}